@@ -1,7 +1,5 @@
 #[macro_use]
 extern crate alloc;
-#[macro_use]
-extern crate ctor;
 
 use std::sync::Arc;
 use std::thread;
@@ -15,6 +13,23 @@ use pi_pointer::GetDataBase;
 use pilf_buddy_alloc::LockFreeHeap;
 use rand::{Rng, SeedableRng};
 
+/// 为每个线程分配一个编号作为 CPU 提示，线程数不超过 CPU 数时相当于每个线程独占一个分片
+#[cfg(feature = "percpu-stats")]
+struct CpuHintImpl;
+
+#[cfg(feature = "percpu-stats")]
+#[crate_interface::impl_interface]
+impl pilf_buddy_alloc::CpuHint for CpuHintImpl {
+    fn current_cpu() -> usize {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        std::thread_local! {
+            static CPU: usize = NEXT.fetch_add(1, Ordering::Relaxed);
+        }
+        CPU.with(|cpu| *cpu)
+    }
+}
+
 const SMALL_SIZE: usize = 8;
 const LARGE_SIZE: usize = 1024 * 1024; // 1M
 const ALIGN: usize = 8;
@@ -39,6 +54,54 @@ pub fn large_alloc<const ORDER: usize>(heap: &LockFreeHeap<ORDER>) {
     }
 }
 
+/// Multithreads alloc and dealloc small objects concurrently
+///
+/// 所有线程都修改统计信息，用于比较统计计数器分片前后的多线程分配耗时
+#[inline]
+pub fn mutil_thread_small_alloc<const ORDER: usize>(heap: &'static LockFreeHeap<ORDER>) {
+    const THREAD_SIZE: usize = 8;
+    const N_ALLOCS: usize = 1000;
+
+    let threads: Vec<_> = (0..THREAD_SIZE)
+        .map(|_| {
+            thread::spawn(move || {
+                for _ in 0..N_ALLOCS {
+                    small_alloc(heap);
+                }
+            })
+        })
+        .collect();
+    for t in threads {
+        t.join().unwrap();
+    }
+}
+
+/// Multithreads alloc and dealloc objects of different orders concurrently
+///
+/// 每个线程只使用一种大小，各线程操作不同的空闲链表，用于比较启用与关闭 `padded-heads` 时的耗时
+#[inline]
+pub fn mutil_thread_multi_order<const ORDER: usize>(heap: &'static LockFreeHeap<ORDER>) {
+    const THREAD_SIZE: usize = 8;
+    const N_ALLOCS: usize = 1000;
+
+    let threads: Vec<_> = (0..THREAD_SIZE)
+        .map(|i| {
+            thread::spawn(move || {
+                let layout = unsafe { Layout::from_size_align_unchecked(SMALL_SIZE << i, ALIGN) };
+                for _ in 0..N_ALLOCS {
+                    unsafe {
+                        let addr = heap.alloc(layout);
+                        heap.dealloc(addr, layout);
+                    }
+                }
+            })
+        })
+        .collect();
+    for t in threads {
+        t.join().unwrap();
+    }
+}
+
 /// Multithreads alloc random sizes of object
 #[inline]
 pub fn mutil_thread_random_size<const ORDER: usize>(heap: &'static LockFreeHeap<ORDER>) {
@@ -140,16 +203,9 @@ pub fn thread_test() {
 }
 
 const ORDER: usize = 33;
-const MACHINE_ALIGN: usize = core::mem::size_of::<usize>();
 /// for now 128M is needed
 /// TODO: reduce memory use
 const KERNEL_HEAP_SIZE: usize = 128 * 1024 * 1024;
-const HEAP_BLOCK: usize = KERNEL_HEAP_SIZE / MACHINE_ALIGN;
-
-#[repr(C, align(0x1000))]
-struct HeapSpace(pub(crate) [usize; HEAP_BLOCK]);
-
-static mut HEAP: HeapSpace = HeapSpace([0; HEAP_BLOCK]);
 
 /// The base address of the heap
 struct GetDataBaseImpl;
@@ -161,26 +217,14 @@ impl GetDataBase for GetDataBaseImpl {
     }
 }
 
-/// Use `LockedHeap` as global allocator
-#[global_allocator]
-static HEAP_ALLOCATOR: LockFreeHeap<ORDER> = LockFreeHeap::<ORDER>::new();
-
-/// Init heap
-///
-/// We need `ctor` here because benchmark is running behind the std enviroment,
-/// which means std will do some initialization before execute `fn main()`.
-/// However, our memory allocator must be init in runtime(use linkedlist, which
-/// can not be evaluated in compile time). And in the initialization phase, heap
-/// memory is needed.
-///
-/// So the solution in this dilemma is to run `fn init_heap()` in initialization phase
-/// rather than in `fn main()`. We need `ctor` to do this.
-#[ctor]
-fn init_heap() {
-    let heap_start = &raw mut HEAP as *mut _ as usize;
-    unsafe {
-        HEAP_ALLOCATOR.init(heap_start, HEAP_BLOCK * MACHINE_ALIGN);
-    }
+pilf_buddy_alloc::static_heap! {
+    /// Use `StaticHeap` as global allocator
+    ///
+    /// std will do some initialization before execute `fn main()`, and heap memory
+    /// is needed in that phase. `StaticHeap` adds its static memory to the heap on
+    /// the first allocation, so no initialization is needed before `fn main()`.
+    #[global_allocator]
+    static HEAP_ALLOCATOR: StaticHeap<ORDER> = KERNEL_HEAP_SIZE;
 }
 
 /// Entry of benchmarks
@@ -188,13 +232,19 @@ pub fn criterion_benchmark(c: &mut Criterion) {
     // run benchmark
     c.bench_function("empty", |b| b.iter(|| {})); // 即使测试函数什么都不做，也会出现bug。
     c.bench_function("small alloc", |b| {
-        b.iter(|| small_alloc(black_box(&HEAP_ALLOCATOR)))
+        b.iter(|| small_alloc(black_box(HEAP_ALLOCATOR.heap())))
     });
     c.bench_function("large alloc", |b| {
-        b.iter(|| large_alloc(black_box(&HEAP_ALLOCATOR)))
+        b.iter(|| large_alloc(black_box(HEAP_ALLOCATOR.heap())))
+    });
+    c.bench_function("mutil thread small alloc", |b| {
+        b.iter(|| mutil_thread_small_alloc(black_box(HEAP_ALLOCATOR.heap())))
+    });
+    c.bench_function("mutil thread multi order", |b| {
+        b.iter(|| mutil_thread_multi_order(black_box(HEAP_ALLOCATOR.heap())))
     });
     c.bench_function("mutil thread random size", |b| {
-        b.iter(|| mutil_thread_random_size(black_box(&HEAP_ALLOCATOR)))
+        b.iter(|| mutil_thread_random_size(black_box(HEAP_ALLOCATOR.heap())))
     });
     c.bench_function("threadtest", |b| b.iter(|| thread_test()));
 }
@@ -0,0 +1,299 @@
+//! 由任意字节串驱动的堆操作序列
+//!
+//! 输入被解码为一系列 `add_to_heap`、`alloc_`、`dealloc_`，区域的起始地址和长度可以是任意字节数，
+//! 以覆盖单元测试中没有出现的对齐情况。每一步之后用 `HeapDump::verify` 检查堆的不变量，
+//! 并检查已分配的块位于区域内、互不重叠、不与空闲块重叠，释放前检查其内容没有被破坏。
+//! 全部释放后，空闲块应当与只加入这些区域时相同。
+//!
+//! 使用 libFuzzer：`cargo fuzz run heap_ops`
+//!
+//! 不使用 libFuzzer：`cargo run --release --bin heap_ops -- [<file-or-dir>...]`，
+//! 依次运行给出的输入文件或目录中的所有文件，例如 `corpus/heap_ops`、`artifacts/heap_ops`；
+//! 没有给出时不断运行随机生成的输入，出错的输入保存为当前目录下的 `crash-heap_ops-<seed>`
+
+#![cfg_attr(fuzzing, no_main)]
+
+use core::alloc::Layout;
+use core::ptr::NonNull;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::OnceLock;
+
+use pilf_buddy_alloc::{HeapDump, LockFreeHeap};
+
+const ORDER: usize = 14;
+/// 每个区域所在槽位的大小，每个槽位最多加入一次，因此区域之间不会重叠
+const SLOT_SIZE: usize = 16 * 1024;
+const SLOTS: usize = 4;
+/// 区域在槽位中的最大起始偏移量
+const MAX_OFFSET: usize = 255;
+/// 区域的最小长度，保证对齐到 16 字节之后起始地址不超过结束地址，满足 `add_to_heap` 的要求
+const MIN_LEN: usize = 15;
+/// 足够容纳整个堆的转储：头部、区域表以及每 16 字节一个空闲块
+const DUMP_SIZE: usize = 1024 + SLOT_SIZE * SLOTS / 16 * 8;
+
+static HEAP_BASE: AtomicUsize = AtomicUsize::new(0);
+
+struct GetDataBaseImpl;
+
+#[crate_interface::impl_interface]
+impl pi_pointer::GetDataBase for GetDataBaseImpl {
+    fn get_data_base() -> usize {
+        HEAP_BASE.load(Ordering::SeqCst)
+    }
+}
+
+/// 输入中的操作都是合法的，任何错误都说明堆有问题
+#[cfg(any(feature = "alloc-bitmap", feature = "poison"))]
+struct HeapErrorHandlerImpl;
+
+#[cfg(any(feature = "alloc-bitmap", feature = "poison"))]
+#[crate_interface::impl_interface]
+impl pilf_buddy_alloc::HeapErrorHandler for HeapErrorHandlerImpl {
+    fn handle_heap_error(err: pilf_buddy_alloc::HeapError) {
+        panic!("heap error: {}", err);
+    }
+}
+
+/// 所有输入共用的内存，只分配一次，数据段基地址即为其起始地址
+fn arena() -> usize {
+    static ARENA: OnceLock<usize> = OnceLock::new();
+    *ARENA.get_or_init(|| {
+        let layout = Layout::from_size_align(SLOT_SIZE * SLOTS, 4096).unwrap();
+        let base = unsafe { std::alloc::alloc(layout) } as usize;
+        assert_ne!(base, 0);
+        HEAP_BASE.store(base, Ordering::SeqCst);
+        base
+    })
+}
+
+struct Input<'a>(&'a [u8]);
+
+impl Input<'_> {
+    fn byte(&mut self) -> Option<usize> {
+        let (&byte, rest) = self.0.split_first()?;
+        self.0 = rest;
+        Some(byte as usize)
+    }
+
+    fn u16(&mut self) -> Option<usize> {
+        Some(self.byte()? | self.byte()? << 8)
+    }
+}
+
+/// 一个已分配的块，内容全部为 fill
+struct Live {
+    addr: usize,
+    layout: Layout,
+    fill: u8,
+}
+
+impl Live {
+    fn end(&self) -> usize {
+        self.addr + self.layout.size()
+    }
+
+    fn check_contents(&self) {
+        let contents =
+            unsafe { core::slice::from_raw_parts(self.addr as *const u8, self.layout.size()) };
+        if let Some(offset) = contents.iter().position(|&byte| byte != self.fill) {
+            panic!(
+                "allocation {:#x} ({:?}) corrupted at offset {}",
+                self.addr, self.layout, offset
+            );
+        }
+    }
+}
+
+/// 每一级空闲链表中的块，按地址排序
+fn free_blocks(heap: &LockFreeHeap<ORDER>, buf: &mut [u8]) -> Vec<Vec<usize>> {
+    let len = heap.dump(buf).unwrap();
+    let dump = HeapDump::parse(&buf[..len]).unwrap();
+    (0..ORDER)
+        .map(|order| {
+            let mut blocks: Vec<usize> = dump
+                .free_blocks(order)
+                .map(|offset| offset + dump.base())
+                .collect();
+            blocks.sort_unstable();
+            blocks
+        })
+        .collect()
+}
+
+fn verify(heap: &LockFreeHeap<ORDER>, live: &[Live], buf: &mut [u8]) {
+    let len = heap.dump(buf).unwrap();
+    let dump = HeapDump::parse(&buf[..len]).unwrap();
+    if let Err(violation) = dump.verify() {
+        panic!("{}\n{}", violation, dump);
+    }
+    for order in 0..ORDER {
+        for block in dump.free_blocks(order).map(|offset| offset + dump.base()) {
+            if let Some(alloc) = live
+                .iter()
+                .find(|alloc| alloc.addr < block + (1 << order) && block < alloc.end())
+            {
+                panic!(
+                    "free block {:#x} (order {}) overlaps allocation {:#x} ({:?})",
+                    block, order, alloc.addr, alloc.layout
+                );
+            }
+        }
+    }
+}
+
+fn run(data: &[u8]) {
+    let base = arena();
+    let heap = LockFreeHeap::<ORDER>::new();
+    let mut input = Input(data);
+    let mut buf = vec![0; DUMP_SIZE];
+    let mut used = [false; SLOTS];
+    let mut regions: Vec<(usize, usize)> = Vec::new();
+    let mut live: Vec<Live> = Vec::new();
+
+    while let Some(op) = input.byte() {
+        match op % 3 {
+            0 => {
+                let (Some(slot), Some(offset), Some(len)) =
+                    (input.byte(), input.byte(), input.u16())
+                else {
+                    break;
+                };
+                let slot = slot % SLOTS;
+                if used[slot] {
+                    continue;
+                }
+                used[slot] = true;
+                let start = base + slot * SLOT_SIZE + offset % (MAX_OFFSET + 1);
+                let end = start + MIN_LEN + len % (SLOT_SIZE - MAX_OFFSET - MIN_LEN + 1);
+                unsafe { heap.add_to_heap(start, end) };
+                regions.push((start, end));
+            }
+            1 => {
+                let (Some(size), Some(align)) = (input.u16(), input.byte()) else {
+                    break;
+                };
+                let layout = Layout::from_size_align(size.max(1), 1 << (align % 13)).unwrap();
+                let Ok(ptr) = heap.alloc_(layout) else {
+                    continue;
+                };
+                let alloc = Live {
+                    addr: ptr.as_ptr() as usize,
+                    layout,
+                    fill: live.len() as u8 ^ 0xa5,
+                };
+                assert!(
+                    alloc.addr.is_multiple_of(layout.align()),
+                    "allocation {:#x} is not aligned to {:?}",
+                    alloc.addr,
+                    layout
+                );
+                assert!(
+                    regions
+                        .iter()
+                        .any(|&(start, end)| start <= alloc.addr && alloc.end() <= end),
+                    "allocation {:#x} ({:?}) is outside all regions",
+                    alloc.addr,
+                    layout
+                );
+                if let Some(other) = live
+                    .iter()
+                    .find(|other| other.addr < alloc.end() && alloc.addr < other.end())
+                {
+                    panic!(
+                        "allocation {:#x} ({:?}) overlaps allocation {:#x} ({:?})",
+                        alloc.addr, layout, other.addr, other.layout
+                    );
+                }
+                unsafe { ptr.as_ptr().write_bytes(alloc.fill, layout.size()) };
+                live.push(alloc);
+            }
+            _ => {
+                let Some(index) = input.byte() else {
+                    break;
+                };
+                if live.is_empty() {
+                    continue;
+                }
+                let alloc = live.swap_remove(index % live.len());
+                alloc.check_contents();
+                heap.dealloc_(NonNull::new(alloc.addr as *mut u8).unwrap(), alloc.layout);
+            }
+        }
+        verify(&heap, &live, &mut buf);
+    }
+
+    for alloc in live.drain(..) {
+        alloc.check_contents();
+        heap.dealloc_(NonNull::new(alloc.addr as *mut u8).unwrap(), alloc.layout);
+    }
+    verify(&heap, &live, &mut buf);
+    assert_eq!(heap.stats_alloc_user(), 0);
+    assert_eq!(heap.stats_alloc_actual(), 0);
+
+    // 堆中的块已经全部释放，可以在同样的内存上建立新的堆
+    let coalesced = free_blocks(&heap, &mut buf);
+    let fresh = LockFreeHeap::<ORDER>::new();
+    for &(start, end) in &regions {
+        unsafe { fresh.add_to_heap(start, end) };
+    }
+    assert_eq!(coalesced, free_blocks(&fresh, &mut buf));
+}
+
+#[cfg(fuzzing)]
+libfuzzer_sys::fuzz_target!(|data: &[u8]| run(data));
+
+#[cfg(not(fuzzing))]
+fn main() {
+    let paths: Vec<std::path::PathBuf> = std::env::args_os().skip(1).map(Into::into).collect();
+    if paths.is_empty() {
+        run_random();
+    }
+    for path in paths {
+        let files = if path.is_dir() {
+            let mut files: Vec<_> = std::fs::read_dir(&path)
+                .unwrap()
+                .map(|entry| entry.unwrap().path())
+                .collect();
+            files.sort();
+            files
+        } else {
+            vec![path]
+        };
+        for file in files {
+            println!("{}", file.display());
+            run(&std::fs::read(&file).unwrap());
+        }
+    }
+}
+
+/// 不断运行随机生成的输入，每个输入由其种子确定
+#[cfg(not(fuzzing))]
+fn run_random() -> ! {
+    let start = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_nanos() as u64;
+    for seed in start.. {
+        // splitmix64
+        let mut state = seed;
+        let mut next = || {
+            state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+            let mut z = state;
+            z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+            z ^ (z >> 31)
+        };
+        let len = next() as usize % 1024;
+        let data: Vec<u8> = (0..len).map(|_| (next() >> 56) as u8).collect();
+        if std::panic::catch_unwind(|| run(&data)).is_err() {
+            let file = format!("crash-heap_ops-{}", seed);
+            std::fs::write(&file, &data).unwrap();
+            eprintln!("input saved to {}", file);
+            std::process::exit(1);
+        }
+        if (seed - start).is_multiple_of(10000) {
+            println!("{} inputs", seed - start);
+        }
+    }
+    unreachable!()
+}
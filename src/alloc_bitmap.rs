@@ -0,0 +1,67 @@
+//! 分配位图
+//!
+//! 每个区域的开头保留一段元数据，为区域中的每个最小块记录一个字节：
+//! 0 代表该位置不是已分配块的起始地址，`order + 1` 代表该位置是一个大小为 2^order 的已分配块，
+//! [`FREED`] 代表该位置的块已被释放。
+//! `dealloc_` 通过该位图检查重复释放、释放无效指针以及 layout 不一致的情况。
+//!
+//! 块合并后，位于大块内部的 [`FREED`] 标记不会被清除，
+//! 因此释放大块内部的地址会被报告为重复释放而不是释放无效指针，但两者都会被检测出来。
+
+use core::sync::atomic::{AtomicU8, Ordering};
+
+use crate::error::HeapError;
+use crate::imp::MIN_BLOCK_SIZE;
+use crate::region::{RegionInfo, RegionTable};
+
+pub(crate) const FREED: u8 = u8::MAX;
+
+/// 在区域 [start, end) 的开头保留位图，并将其清零。
+/// 返回区域可分配部分的起始地址。
+/// SAFETY: [start, end) 需要是有效的、按最小块对齐的内存区域
+pub(crate) unsafe fn reserve(start: usize, end: usize) -> usize {
+    let blocks = (end - start) / MIN_BLOCK_SIZE;
+    let len = (blocks + MIN_BLOCK_SIZE - 1) & !(MIN_BLOCK_SIZE - 1);
+    if len >= end - start {
+        return end;
+    }
+    core::ptr::write_bytes(start as *mut u8, 0, len);
+    start + len
+}
+
+fn slot(region: &RegionInfo, addr: usize) -> &'static AtomicU8 {
+    let index = (addr - region.data) / MIN_BLOCK_SIZE;
+    unsafe { &*((region.start + index) as *const AtomicU8) }
+}
+
+/// 记录 addr 处分配了一个大小为 2^order 的块
+pub(crate) fn mark_alloc(regions: &RegionTable, addr: usize, order: usize) {
+    let region = regions
+        .find(addr)
+        .expect("allocated block outside heap regions");
+    // 块在分配者与释放者之间的传递由使用者同步，位图本身只需保证单个字节的原子性
+    slot(&region, addr).store(order as u8 + 1, Ordering::Relaxed);
+}
+
+/// 检查并记录 addr 处大小为 2^order 的块被释放
+pub(crate) fn mark_free(regions: &RegionTable, addr: usize, order: usize) -> Result<(), HeapError> {
+    let region = regions.find(addr).ok_or(HeapError::InvalidFree { addr })?;
+    if !(addr - region.data).is_multiple_of(MIN_BLOCK_SIZE) {
+        return Err(HeapError::InvalidFree { addr });
+    }
+    match slot(&region, addr).compare_exchange(
+        order as u8 + 1,
+        FREED,
+        Ordering::Relaxed,
+        Ordering::Relaxed,
+    ) {
+        Ok(_) => Ok(()),
+        Err(0) => Err(HeapError::InvalidFree { addr }),
+        Err(FREED) => Err(HeapError::DoubleFree { addr, order }),
+        Err(current) => Err(HeapError::LayoutMismatch {
+            addr,
+            alloc_order: current as usize - 1,
+            free_order: order,
+        }),
+    }
+}
@@ -0,0 +1,150 @@
+//! 读取 `LockFreeHeap::dump` 生成的转储文件，输出碎片分布图、空闲块直方图以及不变量检查的结果
+//!
+//! 用法：`pilf-heap-inspect [--text] [--svg <svg-file>] <dump-file>`
+
+use std::process::ExitCode;
+
+use pilf_buddy_alloc::HeapDump;
+
+/// 碎片分布图中每个区域的宽度
+const MAP_WIDTH: usize = 64;
+/// 直方图的最大宽度
+const BAR_WIDTH: usize = 40;
+
+/// 转储中的偏移量不需要地址转换，但库中的其它代码引用了该接口
+struct GetDataBaseImpl;
+
+#[crate_interface::impl_interface]
+impl pi_pointer::GetDataBase for GetDataBaseImpl {
+    fn get_data_base() -> usize {
+        0
+    }
+}
+
+fn main() -> ExitCode {
+    let mut text = false;
+    let mut svg = None;
+    let mut path = None;
+    let mut args = std::env::args().skip(1);
+    let mut usage = false;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--text" => text = true,
+            "--svg" => match args.next() {
+                Some(file) => svg = Some(file),
+                None => usage = true,
+            },
+            _ if path.is_none() => path = Some(arg),
+            _ => usage = true,
+        }
+    }
+    let Some(path) = path.filter(|_| !usage) else {
+        eprintln!("usage: pilf-heap-inspect [--text] [--svg <svg-file>] <dump-file>");
+        return ExitCode::FAILURE;
+    };
+    let data = match std::fs::read(&path) {
+        Ok(data) => data,
+        Err(err) => {
+            eprintln!("{}: {}", path, err);
+            return ExitCode::FAILURE;
+        }
+    };
+    let dump = match HeapDump::parse(&data) {
+        Ok(dump) => dump,
+        Err(err) => {
+            eprintln!("{}: {}", path, err);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    if text {
+        print!("{}", dump);
+        println!();
+    }
+    if let Some(svg) = svg {
+        let mut out = String::new();
+        dump.render_svg(&mut out).unwrap();
+        if let Err(err) = std::fs::write(&svg, out) {
+            eprintln!("{}: {}", svg, err);
+            return ExitCode::FAILURE;
+        }
+    }
+
+    // 每个空闲块的大小
+    let blocks: Vec<usize> = (0..dump.order())
+        .flat_map(|order| dump.free_blocks(order).map(move |_| 1 << order))
+        .collect();
+
+    print_summary(&dump, &blocks);
+    print_maps(&dump);
+    print_histogram(&dump);
+    if check(&dump) {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
+
+fn print_summary(dump: &HeapDump, blocks: &[usize]) {
+    let stats = dump.stats();
+    // 转储可能被篡改，空闲块的总大小可能超出 usize
+    let free = blocks
+        .iter()
+        .fold(0usize, |free, &size| free.saturating_add(size));
+    let largest = blocks.iter().copied().max().unwrap_or(0);
+    println!("base       {:#x}", dump.base());
+    println!("order      {}", dump.order());
+    println!("total      {}", stats.total);
+    println!("allocated  {} (user {})", stats.allocated, stats.user);
+    println!("free       {} in {} blocks", free, blocks.len());
+    if free != 0 {
+        // 最大空闲块之外的空闲内存所占的比例
+        println!(
+            "fragmentation {:.1}% (largest free block {})",
+            100.0 * (free - largest) as f64 / free as f64,
+            largest
+        );
+    }
+    println!();
+}
+
+fn print_maps(dump: &HeapDump) {
+    let mut map = String::new();
+    dump.render_ascii(&mut map, MAP_WIDTH).unwrap();
+    println!("{}", map);
+}
+
+fn print_histogram(dump: &HeapDump) {
+    let counts: Vec<usize> = (0..dump.order())
+        .map(|order| dump.free_blocks(order).count())
+        .collect();
+    let max = counts.iter().copied().max().unwrap_or(0).max(1);
+    println!("free blocks by order");
+    for (order, &count) in counts.iter().enumerate() {
+        if count == 0 {
+            continue;
+        }
+        let bar = "*".repeat(count.div_ceil(max.div_ceil(BAR_WIDTH)));
+        println!(
+            "  {:>2} {:>10} x{:<8} {}",
+            order,
+            1usize << order,
+            count,
+            bar
+        );
+    }
+    println!();
+}
+
+/// 输出转储中违反堆的不变量之处，返回是否全部满足
+fn check(dump: &HeapDump) -> bool {
+    let mut ok = true;
+    dump.violations(|violation| {
+        println!("violation: {}", violation);
+        ok = false;
+    });
+    if ok {
+        println!("no invariant violations");
+    }
+    ok
+}
@@ -0,0 +1,40 @@
+//! 按缓存行对齐的包装类型，使被包装的数据独占一个缓存行
+
+use core::ops::Deref;
+
+/// 将 `T` 对齐并填充到 64 字节
+#[repr(align(64))]
+pub(crate) struct CachePadded<T>(T);
+
+impl<T> CachePadded<T> {
+    pub(crate) const fn new(value: T) -> Self {
+        Self(value)
+    }
+}
+
+impl<T> Deref for CachePadded<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+/// 空闲链表头的包装类型
+/// 启用 `padded-heads` 时与 [`CachePadded`] 相同，否则不进行填充，使各个链表头紧密排列
+#[cfg_attr(feature = "padded-heads", repr(align(64)))]
+pub(crate) struct HeadPadded<T>(T);
+
+impl<T> HeadPadded<T> {
+    pub(crate) const fn new(value: T) -> Self {
+        Self(value)
+    }
+}
+
+impl<T> Deref for HeadPadded<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
@@ -0,0 +1,434 @@
+//! 堆的转储格式
+//!
+//! `LockFreeHeap::dump` 将堆的区域、各级空闲链表中的块以及统计信息写入一段紧凑的二进制数据，
+//! [`HeapDump`] 用于读取这样的数据。所有地址都以相对于数据段基地址的偏移量存储，
+//! 因此转储与进程的地址空间无关，可以离线分析。
+//!
+//! 二进制格式中的每个字段都是小端序的 u64：
+//!
+//! ```text
+//! magic "PILFDUMP"
+//! version
+//! base                         转储时的数据段基地址，仅供参考
+//! order                        空闲链表的级数
+//! user allocated total         统计信息
+//! region_count
+//! (start data end) * region_count
+//! (count block * count) * order   第 i 组为大小为 2^i 的空闲块
+//! ```
+//!
+//! 转储时不会暂停其它线程的分配与释放，因此与之并发时，转储不一定对应堆在某一时刻的准确状态。
+
+use core::fmt;
+
+use crate::region::MAX_REGIONS;
+
+const MAGIC: [u8; 8] = *b"PILFDUMP";
+const VERSION: u64 = 1;
+const WORD: usize = 8;
+
+/// 转储或读取转储时的错误
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum DumpError {
+    /// 缓冲区太小，needed 为写入完整转储所需的字节数
+    BufferTooSmall { needed: usize },
+    /// 不是有效的转储数据
+    Invalid,
+}
+
+impl fmt::Display for DumpError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DumpError::BufferTooSmall { needed } => {
+                write!(f, "buffer too small, {} bytes needed", needed)
+            }
+            DumpError::Invalid => write!(f, "invalid heap dump"),
+        }
+    }
+}
+
+/// 转储的接收者。堆按以下顺序调用各个方法：
+/// `header`、`stats`、`regions_begin`、每个区域的 `region`、`regions_end`，
+/// 然后对每一级空闲链表调用 `free_list_begin`、每个块的 `free_block`、`free_list_end`
+pub(crate) trait DumpSink {
+    fn header(&mut self, base: usize, order: usize);
+    fn stats(&mut self, stats: DumpStats);
+    fn regions_begin(&mut self);
+    fn region(&mut self, region: DumpRegion);
+    fn regions_end(&mut self);
+    fn free_list_begin(&mut self, order: usize);
+    fn free_block(&mut self, offset: usize);
+    fn free_list_end(&mut self);
+}
+
+/// 向缓冲区中写入二进制转储，缓冲区不足时继续计算所需的长度
+pub(crate) struct BinaryWriter<'a> {
+    buf: &'a mut [u8],
+    len: usize,
+    // 正在写入的区域表或空闲链表的长度字段的位置，以及已经写入的项数
+    count_pos: usize,
+    count: usize,
+}
+
+impl<'a> BinaryWriter<'a> {
+    pub(crate) fn new(buf: &'a mut [u8]) -> Self {
+        Self {
+            buf,
+            len: 0,
+            count_pos: 0,
+            count: 0,
+        }
+    }
+
+    fn bytes(&mut self, bytes: &[u8]) {
+        if let Some(dst) = self.buf.get_mut(self.len..self.len + bytes.len()) {
+            dst.copy_from_slice(bytes);
+        }
+        self.len += bytes.len();
+    }
+
+    fn word(&mut self, value: usize) {
+        self.bytes(&(value as u64).to_le_bytes());
+    }
+
+    /// 预留长度字段，在 end_count 中回填
+    fn begin_count(&mut self) {
+        self.count_pos = self.len;
+        self.count = 0;
+        self.word(0);
+    }
+
+    fn end_count(&mut self) {
+        let pos = self.count_pos;
+        if let Some(dst) = self.buf.get_mut(pos..pos + WORD) {
+            dst.copy_from_slice(&(self.count as u64).to_le_bytes());
+        }
+    }
+
+    pub(crate) fn finish(self) -> Result<usize, DumpError> {
+        if self.len <= self.buf.len() {
+            Ok(self.len)
+        } else {
+            Err(DumpError::BufferTooSmall { needed: self.len })
+        }
+    }
+}
+
+impl DumpSink for BinaryWriter<'_> {
+    fn header(&mut self, base: usize, order: usize) {
+        self.bytes(&MAGIC);
+        self.word(VERSION as usize);
+        self.word(base);
+        self.word(order);
+    }
+
+    fn stats(&mut self, stats: DumpStats) {
+        self.word(stats.user);
+        self.word(stats.allocated);
+        self.word(stats.total);
+    }
+
+    fn regions_begin(&mut self) {
+        self.begin_count();
+    }
+
+    fn region(&mut self, region: DumpRegion) {
+        self.word(region.start);
+        self.word(region.data);
+        self.word(region.end);
+        self.count += 1;
+    }
+
+    fn regions_end(&mut self) {
+        self.end_count();
+    }
+
+    fn free_list_begin(&mut self, _order: usize) {
+        self.begin_count();
+    }
+
+    fn free_block(&mut self, offset: usize) {
+        self.word(offset);
+        self.count += 1;
+    }
+
+    fn free_list_end(&mut self) {
+        self.end_count();
+    }
+}
+
+/// 以文本格式写入转储，空的空闲链表不输出
+pub(crate) struct TextWriter<'a, W: fmt::Write> {
+    w: &'a mut W,
+    result: fmt::Result,
+    order: usize,
+    // 当前空闲链表是否已经输出过块
+    started: bool,
+}
+
+impl<'a, W: fmt::Write> TextWriter<'a, W> {
+    pub(crate) fn new(w: &'a mut W) -> Self {
+        Self {
+            w,
+            result: Ok(()),
+            order: 0,
+            started: false,
+        }
+    }
+
+    fn write(&mut self, args: fmt::Arguments) {
+        if self.result.is_ok() {
+            self.result = self.w.write_fmt(args);
+        }
+    }
+
+    pub(crate) fn finish(self) -> fmt::Result {
+        self.result
+    }
+}
+
+impl<W: fmt::Write> DumpSink for TextWriter<'_, W> {
+    fn header(&mut self, base: usize, order: usize) {
+        self.write(format_args!("pilf heap dump v{}\n", VERSION));
+        self.write(format_args!("base {:#x}\n", base));
+        self.write(format_args!("order {}\n", order));
+    }
+
+    fn stats(&mut self, stats: DumpStats) {
+        self.write(format_args!(
+            "stats user={} allocated={} total={}\n",
+            stats.user, stats.allocated, stats.total
+        ));
+    }
+
+    fn regions_begin(&mut self) {}
+
+    fn region(&mut self, region: DumpRegion) {
+        self.write(format_args!(
+            "region start={:#x} data={:#x} end={:#x}\n",
+            region.start, region.data, region.end
+        ));
+    }
+
+    fn regions_end(&mut self) {}
+
+    fn free_list_begin(&mut self, order: usize) {
+        self.order = order;
+        self.started = false;
+    }
+
+    fn free_block(&mut self, offset: usize) {
+        if !self.started {
+            self.started = true;
+            let order = self.order;
+            self.write(format_args!("free {}:", order));
+        }
+        self.write(format_args!(" {:#x}", offset));
+    }
+
+    fn free_list_end(&mut self) {
+        if self.started {
+            self.write(format_args!("\n"));
+        }
+    }
+}
+
+/// 转储中的统计信息
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DumpStats {
+    pub user: usize,
+    pub allocated: usize,
+    pub total: usize,
+}
+
+/// 转储中的一段内存区域，均为偏移量
+/// [start, data) 为区域的元数据部分，[data, end) 为可分配部分
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DumpRegion {
+    pub start: usize,
+    pub data: usize,
+    pub end: usize,
+}
+
+/// 读取一段转储数据，不进行复制
+#[derive(Clone, Copy, Debug)]
+pub struct HeapDump<'a> {
+    base: usize,
+    order: usize,
+    stats: DumpStats,
+    regions: &'a [u8],
+    free_lists: &'a [u8],
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], DumpError> {
+        if len > self.data.len() {
+            return Err(DumpError::Invalid);
+        }
+        let (head, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(head)
+    }
+
+    fn word(&mut self) -> Result<usize, DumpError> {
+        let bytes = self.take(WORD)?;
+        let value = u64::from_le_bytes(bytes.try_into().unwrap());
+        usize::try_from(value).map_err(|_| DumpError::Invalid)
+    }
+
+    fn words(&mut self, count: usize) -> Result<&'a [u8], DumpError> {
+        self.take(count.checked_mul(WORD).ok_or(DumpError::Invalid)?)
+    }
+}
+
+fn words(bytes: &[u8]) -> impl Iterator<Item = usize> + '_ {
+    bytes
+        .chunks_exact(WORD)
+        .map(|word| u64::from_le_bytes(word.try_into().unwrap()) as usize)
+}
+
+/// 每个区域满足 start <= data <= end，并且区域之间互不重叠
+fn regions_valid(regions: &[u8]) -> bool {
+    let region = |i: usize| {
+        let mut words = words(&regions[i * 3 * WORD..(i + 1) * 3 * WORD]);
+        (
+            words.next().unwrap(),
+            words.next().unwrap(),
+            words.next().unwrap(),
+        )
+    };
+    let count = regions.len() / (3 * WORD);
+    (0..count).all(|i| {
+        let (start, data, end) = region(i);
+        start <= data
+            && data <= end
+            && (0..i).all(|j| {
+                let (other_start, _, other_end) = region(j);
+                end <= other_start || other_end <= start
+            })
+    })
+}
+
+impl<'a> HeapDump<'a> {
+    /// 检查并读取转储数据。转储可能来自不可信的文件，
+    /// 级数超过 usize::BITS、区域的边界颠倒或区域互相重叠时返回 `DumpError::Invalid`
+    pub fn parse(data: &'a [u8]) -> Result<Self, DumpError> {
+        let mut reader = Reader { data };
+        if reader.take(MAGIC.len())? != MAGIC || reader.word()? != VERSION as usize {
+            return Err(DumpError::Invalid);
+        }
+        let base = reader.word()?;
+        // 大小为 2^i 的块中 i 小于 usize::BITS
+        let order = reader.word()?;
+        if order > usize::BITS as usize {
+            return Err(DumpError::Invalid);
+        }
+        let stats = DumpStats {
+            user: reader.word()?,
+            allocated: reader.word()?,
+            total: reader.word()?,
+        };
+        // 堆最多记录 MAX_REGIONS 个区域，因此区域之间两两比较的开销有限
+        let region_count = reader.word()?;
+        if region_count > MAX_REGIONS {
+            return Err(DumpError::Invalid);
+        }
+        let regions = reader.words(region_count * 3)?;
+        if !regions_valid(regions) {
+            return Err(DumpError::Invalid);
+        }
+        let free_lists = reader.data;
+        for _ in 0..order {
+            let count = reader.word()?;
+            reader.words(count)?;
+        }
+        if !reader.data.is_empty() {
+            return Err(DumpError::Invalid);
+        }
+        Ok(Self {
+            base,
+            order,
+            stats,
+            regions,
+            free_lists,
+        })
+    }
+
+    /// 转储时的数据段基地址
+    pub fn base(&self) -> usize {
+        self.base
+    }
+
+    /// 空闲链表的级数
+    pub fn order(&self) -> usize {
+        self.order
+    }
+
+    pub fn stats(&self) -> DumpStats {
+        self.stats
+    }
+
+    pub fn regions(&self) -> impl Iterator<Item = DumpRegion> + 'a {
+        let mut words = words(self.regions);
+        core::iter::from_fn(move || {
+            Some(DumpRegion {
+                start: words.next()?,
+                data: words.next()?,
+                end: words.next()?,
+            })
+        })
+    }
+
+    /// 大小为 2^order 的空闲块的偏移量，order 不小于 `order()` 时为空
+    pub fn free_blocks(&self, order: usize) -> impl Iterator<Item = usize> + 'a {
+        let mut reader = Reader {
+            data: self.free_lists,
+        };
+        let mut blocks: &[u8] = &[];
+        if order >= self.order {
+            return words(blocks);
+        }
+        for i in 0..=order {
+            // parse 中已经检查过长度
+            let count = reader.word().unwrap();
+            let list = reader.words(count).unwrap();
+            if i == order {
+                blocks = list;
+            }
+        }
+        words(blocks)
+    }
+}
+
+impl HeapDump<'_> {
+    fn replay(&self, sink: &mut impl DumpSink) {
+        sink.header(self.base, self.order);
+        sink.stats(self.stats);
+        sink.regions_begin();
+        for region in self.regions() {
+            sink.region(region);
+        }
+        sink.regions_end();
+        for order in 0..self.order {
+            sink.free_list_begin(order);
+            for block in self.free_blocks(order) {
+                sink.free_block(block);
+            }
+            sink.free_list_end();
+        }
+    }
+}
+
+impl fmt::Display for HeapDump<'_> {
+    /// 可读的文本格式，与 `LockFreeHeap::dump_text` 的输出相同
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut writer = TextWriter::new(f);
+        self.replay(&mut writer);
+        writer.finish()
+    }
+}
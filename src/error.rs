@@ -0,0 +1,71 @@
+//! 堆检查发现的错误，以及报告错误的接口
+//!
+//! 检查功能由 cargo feature 开启，开启后需要像 `GetDataBase` 一样，
+//! 使用 `crate_interface::impl_interface` 实现 [`HeapErrorHandler`]。
+
+use core::fmt;
+
+/// 堆检查发现的错误
+/// 其中的地址均为实际地址，order 为块大小的以 2 为底的对数
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum HeapError {
+    /// 释放了一个已经被释放的块
+    DoubleFree { addr: usize, order: usize },
+    /// 释放了一个不是由 `alloc_` 返回的指针
+    InvalidFree { addr: usize },
+    /// 释放时 layout 对应的块大小与分配时不一致
+    LayoutMismatch {
+        addr: usize,
+        alloc_order: usize,
+        free_order: usize,
+    },
+    /// 空闲块在释放后被写入，offset 为第一个被修改的字节在块中的偏移量
+    UseAfterFree { addr: usize, offset: usize },
+    /// 写入超出了分配时的大小，offset 为红区中第一个被修改的字节在块中的偏移量
+    RedZoneOverflow { addr: usize, offset: usize },
+}
+
+impl fmt::Display for HeapError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            HeapError::DoubleFree { addr, order } => {
+                write!(f, "double free of block {:#x} (order {})", addr, order)
+            }
+            HeapError::InvalidFree { addr } => {
+                write!(f, "free of pointer {:#x} not returned by alloc", addr)
+            }
+            HeapError::LayoutMismatch {
+                addr,
+                alloc_order,
+                free_order,
+            } => write!(
+                f,
+                "block {:#x} allocated with order {} but freed with order {}",
+                addr, alloc_order, free_order
+            ),
+            HeapError::UseAfterFree { addr, offset } => write!(
+                f,
+                "free block {:#x} was written at offset {} after free",
+                addr, offset
+            ),
+            HeapError::RedZoneOverflow { addr, offset } => write!(
+                f,
+                "block {:#x} was written beyond its size at offset {}",
+                addr, offset
+            ),
+        }
+    }
+}
+
+/// 堆错误的处理函数，由使用者实现
+#[crate_interface::def_interface]
+pub trait HeapErrorHandler {
+    /// 报告一个错误。函数返回后，出错的释放操作会被放弃；
+    /// `UseAfterFree` 和 `RedZoneOverflow` 只用于报告，分配和释放照常进行。
+    fn handle_heap_error(err: HeapError);
+}
+
+pub(crate) fn report(err: HeapError) {
+    crate_interface::call_interface!(HeapErrorHandler::handle_heap_error, err)
+}
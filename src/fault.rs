@@ -0,0 +1,36 @@
+//! 故障注入
+//!
+//! 启用 `fault-injection` 后，链表和堆在若干并发敏感的位置调用使用者实现的 [`FaultInjector`]，
+//! 使用者可以在回调中让当前线程暂停或让出 CPU，从而确定性地重现特定的线程交错。
+//! 与 `GetDataBase` 一样，需要使用 `crate_interface::impl_interface` 实现该接口。
+//! 该功能只用于测试，回调中不应使用同一个堆分配内存。
+
+/// 故障注入点，其中的地址均为实际地址
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum FaultPoint {
+    /// `search_with_ptr` 找到 left_node 与 right_node 之后、检查二者是否相邻之前。
+    /// item 为所找的项，left 为 left_node，right 为 right_node，没有 right_node 时为 NULL_PTR
+    Search {
+        item: usize,
+        left: usize,
+        right: usize,
+    },
+    /// `pop` 标记 node（逻辑删除）之后、将其从链表上删去（物理删除）之前
+    PopMarked { node: usize },
+    /// `delete` 标记 node（逻辑删除）之后、将其从链表上删去（物理删除）之前
+    DeleteMarked { node: usize },
+    /// `alloc_` 将大小为 2^order 的块 addr 切分之后、将后一半插入空闲链表之前
+    AllocSplit { addr: usize, order: usize },
+}
+
+/// 故障注入的回调函数，由使用者实现
+#[crate_interface::def_interface]
+pub trait FaultInjector {
+    /// 线程到达 point 时调用，函数返回后线程继续执行
+    fn on_fault_point(point: FaultPoint);
+}
+
+pub(crate) fn inject(point: FaultPoint) {
+    crate_interface::call_interface!(FaultInjector::on_fault_point, point)
+}
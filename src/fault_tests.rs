@@ -0,0 +1,297 @@
+//! 用故障注入确定性地重现链表与堆操作之间的线程交错
+//!
+//! 测试线程通过 [`pause_at`] 让指定名字的线程在第一个满足条件的注入点暂停，
+//! 用 [`wait_paused`] 等待其到达，在此期间执行其它操作，最后用 [`resume`] 让其继续。
+//! 线程名在所有测试之间唯一，因此并行运行的测试不会互相影响。
+
+use std::sync::{Condvar, Mutex};
+use std::thread::{self, Scope, ScopedJoinHandle};
+
+use crate::heap_tests::lock_heap_base;
+use crate::{FaultInjector, FaultPoint, LinkedList};
+
+type Matcher = Box<dyn Fn(&FaultPoint) -> bool + Send>;
+
+enum State {
+    /// 等待到达满足条件的注入点
+    Armed(Matcher),
+    /// 已在 point 处暂停
+    Paused(FaultPoint),
+    /// 可以继续执行
+    Resumed,
+}
+
+/// 线程名与其状态
+static THREADS: Mutex<Vec<(&'static str, State)>> = Mutex::new(Vec::new());
+static CHANGED: Condvar = Condvar::new();
+
+struct FaultInjectorImpl;
+
+#[crate_interface::impl_interface]
+impl FaultInjector for FaultInjectorImpl {
+    fn on_fault_point(point: FaultPoint) {
+        let current = thread::current();
+        let Some(name) = current.name() else {
+            return;
+        };
+        let mut threads = THREADS.lock().unwrap();
+        let Some(state) = threads
+            .iter_mut()
+            .find(|(thread, _)| *thread == name)
+            .map(|(_, state)| state)
+        else {
+            return;
+        };
+        match state {
+            State::Armed(matches) if matches(&point) => *state = State::Paused(point),
+            _ => return,
+        }
+        CHANGED.notify_all();
+        let mut threads = CHANGED
+            .wait_while(threads, |threads| {
+                !threads
+                    .iter()
+                    .any(|(thread, state)| *thread == name && matches!(state, State::Resumed))
+            })
+            .unwrap();
+        threads.retain(|(thread, _)| *thread != name);
+    }
+}
+
+/// 让名为 thread 的线程在第一个满足 matches 的注入点暂停
+fn pause_at(thread: &'static str, matches: impl Fn(&FaultPoint) -> bool + Send + 'static) {
+    let mut threads = THREADS.lock().unwrap();
+    assert!(threads.iter().all(|(name, _)| *name != thread));
+    threads.push((thread, State::Armed(Box::new(matches))));
+}
+
+/// 等待名为 thread 的线程暂停，返回其所在的注入点
+fn wait_paused(thread: &'static str) -> FaultPoint {
+    let threads = THREADS.lock().unwrap();
+    let threads = CHANGED
+        .wait_while(threads, |threads| {
+            !threads
+                .iter()
+                .any(|(name, state)| *name == thread && matches!(state, State::Paused(_)))
+        })
+        .unwrap();
+    threads
+        .iter()
+        .find_map(|(name, state)| match state {
+            State::Paused(point) if *name == thread => Some(*point),
+            _ => None,
+        })
+        .unwrap()
+}
+
+/// 让暂停的线程继续执行
+fn resume(thread: &'static str) {
+    let mut threads = THREADS.lock().unwrap();
+    for (name, state) in threads.iter_mut() {
+        if *name == thread {
+            assert!(matches!(state, State::Paused(_)));
+            *state = State::Resumed;
+        }
+    }
+    CHANGED.notify_all();
+}
+
+fn spawn_named<'scope, T: Send + 'scope>(
+    scope: &'scope Scope<'scope, '_>,
+    name: &'static str,
+    f: impl FnOnce() -> T + Send + 'scope,
+) -> ScopedJoinHandle<'scope, T> {
+    thread::Builder::new()
+        .name(name.into())
+        .spawn_scoped(scope, f)
+        .unwrap()
+}
+
+fn node(value: &mut [usize; 2]) -> *mut () {
+    value as *mut [usize; 2] as *mut ()
+}
+
+/// pop 标记了节点但尚未将其删去时，delete 同一个节点应返回 false
+#[test]
+fn fault_pop_delete_same() {
+    let _base = lock_heap_base(0);
+    const POP: &str = "fault_pop_delete_same/pop";
+
+    let mut value1 = [0; 2];
+    let mut value2 = [0; 2];
+    let (node1, node2) = (node(&mut value1), node(&mut value2));
+    let list = LinkedList::new();
+    unsafe {
+        list.push(node1);
+        list.push(node2);
+    }
+
+    pause_at(POP, |point| matches!(point, FaultPoint::PopMarked { .. }));
+    thread::scope(|s| {
+        let pop = spawn_named(s, POP, || list.pop().map(|node| node as usize));
+        assert_eq!(
+            wait_paused(POP),
+            FaultPoint::PopMarked {
+                node: node2 as usize
+            }
+        );
+        assert!(!list.delete(node2));
+        resume(POP);
+        assert_eq!(pop.join().unwrap(), Some(node2 as usize));
+    });
+
+    assert_eq!(list.pop(), Some(node1));
+    assert_eq!(list.pop(), None);
+}
+
+/// pop 标记了第一个节点但尚未将其删去时，另一个 pop 应跳过该节点并帮助将其删去
+#[test]
+fn fault_pop_pop() {
+    let _base = lock_heap_base(0);
+    const POP: &str = "fault_pop_pop/pop";
+
+    let mut value1 = [0; 2];
+    let mut value2 = [0; 2];
+    let (node1, node2) = (node(&mut value1), node(&mut value2));
+    let list = LinkedList::new();
+    unsafe {
+        list.push(node1);
+        list.push(node2);
+    }
+
+    pause_at(POP, |point| matches!(point, FaultPoint::PopMarked { .. }));
+    thread::scope(|s| {
+        let pop = spawn_named(s, POP, || list.pop().map(|node| node as usize));
+        wait_paused(POP);
+        assert_eq!(list.pop(), Some(node1));
+        assert_eq!(list.pop(), None);
+        resume(POP);
+        assert_eq!(pop.join().unwrap(), Some(node2 as usize));
+    });
+    assert!(list.is_empty());
+}
+
+/// delete 找到 left_node 之后，left_node 被另一个线程的 pop 标记。
+/// delete 需要重新查找，两个节点都只能被删除一次
+#[test]
+fn fault_delete_marked_left() {
+    let _base = lock_heap_base(0);
+    const DELETE: &str = "fault_delete_marked_left/delete";
+    const POP: &str = "fault_delete_marked_left/pop";
+
+    let mut value1 = [0; 2];
+    let mut value2 = [0; 2];
+    let (node1, node2) = (node(&mut value1), node(&mut value2));
+    let list = LinkedList::new();
+    unsafe {
+        list.push(node1);
+        list.push(node2);
+    }
+
+    // 裸指针不能在线程间传递
+    let target = node1 as usize;
+    pause_at(
+        DELETE,
+        move |point| matches!(*point, FaultPoint::Search { item, .. } if item == target),
+    );
+    pause_at(POP, |point| matches!(point, FaultPoint::PopMarked { .. }));
+    thread::scope(|s| {
+        let delete = spawn_named(s, DELETE, || list.delete(target as *mut ()));
+        assert_eq!(
+            wait_paused(DELETE),
+            FaultPoint::Search {
+                item: node1 as usize,
+                left: node2 as usize,
+                right: node1 as usize
+            }
+        );
+        let pop = spawn_named(s, POP, || list.pop().map(|node| node as usize));
+        wait_paused(POP);
+        resume(DELETE);
+        assert!(delete.join().unwrap());
+        resume(POP);
+        assert_eq!(pop.join().unwrap(), Some(node2 as usize));
+    });
+    assert!(list.is_empty());
+}
+
+/// 分配时大块切分完成之前，其它线程取不到空闲块，见 `alloc_` 中的说明
+#[cfg(not(any(feature = "alloc-bitmap", feature = "red-zones")))]
+#[test]
+fn fault_alloc_split() {
+    use crate::LockFreeHeap;
+    use core::alloc::Layout;
+
+    const ALLOC: &str = "fault_alloc_split/alloc";
+
+    #[repr(align(64))]
+    struct Space([u8; 64]);
+    let space = Space([0; 64]);
+    let start = space.0.as_ptr() as usize;
+    let heap = LockFreeHeap::<8>::new();
+    let _base = lock_heap_base(start);
+    unsafe { heap.add_to_heap(start, start + 64) };
+    let layout = Layout::from_size_align(16, 1).unwrap();
+
+    pause_at(ALLOC, |point| {
+        matches!(point, FaultPoint::AllocSplit { .. })
+    });
+    thread::scope(|s| {
+        let alloc = spawn_named(s, ALLOC, || {
+            heap.alloc_(layout).map(|addr| addr.as_ptr() as usize)
+        });
+        assert_eq!(
+            wait_paused(ALLOC),
+            FaultPoint::AllocSplit {
+                addr: start,
+                order: 6
+            }
+        );
+        assert!(heap.alloc_(layout).is_err());
+        resume(ALLOC);
+        assert_eq!(alloc.join().unwrap(), Ok(start));
+    });
+    assert!(heap.alloc_(layout).is_ok());
+}
+
+/// 两个互为伙伴的块被同时释放时，一个线程查找伙伴之后、插入链表之前，另一个线程释放了伙伴，
+/// 两个线程都没有找到对方而各自插入链表。插入后的再次检查应当将两个块合并
+#[cfg(not(any(feature = "alloc-bitmap", feature = "red-zones")))]
+#[test]
+fn fault_dealloc_dealloc_buddies() {
+    use crate::LockFreeHeap;
+    use core::alloc::Layout;
+    use core::ptr::NonNull;
+
+    const DEALLOC: &str = "fault_dealloc_dealloc_buddies/dealloc";
+
+    #[repr(align(32))]
+    struct Space([u8; 32]);
+    let space = Space([0; 32]);
+    let start = space.0.as_ptr() as usize;
+    let heap = LockFreeHeap::<8>::new();
+    let _base = lock_heap_base(start);
+    unsafe { heap.add_to_heap(start, start + 32) };
+    let layout = Layout::from_size_align(16, 1).unwrap();
+    let first = heap.alloc_(layout).unwrap().as_ptr() as usize;
+    let second = heap.alloc_(layout).unwrap().as_ptr() as usize;
+    assert_eq!(first ^ second, 16);
+
+    pause_at(
+        DEALLOC,
+        move |point| matches!(*point, FaultPoint::Search { item, .. } if item == second),
+    );
+    thread::scope(|s| {
+        let dealloc = spawn_named(s, DEALLOC, || {
+            heap.dealloc_(NonNull::new(first as *mut u8).unwrap(), layout)
+        });
+        wait_paused(DEALLOC);
+        heap.dealloc_(NonNull::new(second as *mut u8).unwrap(), layout);
+        resume(DEALLOC);
+        dealloc.join().unwrap();
+    });
+    assert_eq!(heap.stats_alloc_actual(), 0);
+
+    let whole = Layout::from_size_align(32, 1).unwrap();
+    assert!(heap.alloc_(whole).is_ok(), "buddies were not merged");
+}
@@ -0,0 +1,23 @@
+//! 内存不足时扩展堆
+//!
+//! 启用 `heap-grow` 后，需要像 `GetDataBase` 一样，
+//! 使用 `crate_interface::impl_interface` 实现 [`HeapGrow`]。
+//! 分配找不到空闲块时，堆调用 [`HeapGrow::grow_heap`] 获取一段新的内存，用 `add_to_heap` 加入后重试。
+//! 同一时刻每个堆只有一个线程在扩展，其它内存不足的线程等待其完成后重试分配。
+
+use core::alloc::Layout;
+
+/// 扩展堆的回调函数，由使用者实现，对应 buddy_system_allocator 中 `LockedHeapWithRescue` 的 rescue 函数
+#[crate_interface::def_interface]
+pub trait HeapGrow {
+    /// 返回一段新的内存 [start, end)，将由堆通过 `add_to_heap` 加入；无法扩展时返回 None。
+    /// 返回的内存应当能够满足 layout，否则堆会再次调用该函数。
+    /// 堆的区域表已满（`MAX_REGIONS` 个区域）时不再调用该函数，分配直接失败；
+    /// 与其它线程的 `add_to_heap` 并发而使区域表在调用期间变满时，返回的内存不会加入堆。
+    /// 该函数在分配的过程中调用，不应在其中使用同一个堆分配内存
+    fn grow_heap(layout: Layout) -> Option<(usize, usize)>;
+}
+
+pub(crate) fn grow(layout: Layout) -> Option<(usize, usize)> {
+    crate_interface::call_interface!(HeapGrow::grow_heap, layout)
+}
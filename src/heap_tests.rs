@@ -3,14 +3,14 @@ use core::{
     alloc::{GlobalAlloc, Layout},
     sync::atomic::{AtomicUsize, Ordering},
 };
-use ctor::ctor;
 use pi_pointer::GetDataBase;
+use std::sync::{Mutex, MutexGuard, PoisonError};
 use std::thread::{spawn, JoinHandle};
 
 /// 这个实现是用来进行单元测试的，list_test 也是用这个函数
 struct GetDataBaseImpl;
 
-static HEAP_BASE: AtomicUsize = AtomicUsize::new(0);
+pub(crate) static HEAP_BASE: AtomicUsize = AtomicUsize::new(0);
 
 #[crate_interface::impl_interface]
 impl GetDataBase for GetDataBaseImpl {
@@ -19,6 +19,21 @@ impl GetDataBase for GetDataBaseImpl {
     }
 }
 
+/// 同一个测试二进制中的所有测试共享 `HEAP_BASE`，链表节点按存入时的基地址编码为偏移量，
+/// 测试运行期间基地址被其它测试修改，会使节点解码到错误的地址。
+/// 因此依赖基地址的测试都通过 [`lock_heap_base`] 独占它，直到测试结束
+static HEAP_BASE_LOCK: Mutex<()> = Mutex::new(());
+
+/// 独占 `HEAP_BASE` 并将其设为 base，返回的守卫需要保持到测试结束
+pub(crate) fn lock_heap_base(base: usize) -> MutexGuard<'static, ()> {
+    // should_panic 的测试会在持有锁时 panic，此时基地址本身仍然可用
+    let guard = HEAP_BASE_LOCK
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner);
+    HEAP_BASE.store(base, Ordering::SeqCst);
+    guard
+}
+
 #[test]
 fn test_empty_heap() {
     let heap = LockFreeHeap::<32>::new();
@@ -31,7 +46,7 @@ fn test_heap_add() {
     assert!(heap.alloc_(Layout::from_size_align(1, 1).unwrap()).is_err());
 
     let space: [usize; 100] = [0; 100];
-    HEAP_BASE.store(space.as_ptr() as usize, Ordering::SeqCst);
+    let _base = lock_heap_base(space.as_ptr() as usize);
     unsafe {
         heap.add_to_heap(space.as_ptr() as usize, space.as_ptr().add(100) as usize);
     }
@@ -47,7 +62,7 @@ fn test_heap_add_large() {
 
     // 512 bytes of space
     let space: [usize; 64] = [0; 64];
-    HEAP_BASE.store(space.as_ptr() as usize, Ordering::SeqCst);
+    let _base = lock_heap_base(space.as_ptr() as usize);
     unsafe {
         heap.add_to_heap(space.as_ptr() as usize, space.as_ptr().add(64) as usize);
     }
@@ -59,7 +74,7 @@ fn test_heap_add_large() {
 fn test_heap_oom() {
     let heap = LockFreeHeap::<32>::new();
     let space: [usize; 100] = [0; 100];
-    HEAP_BASE.store(space.as_ptr() as usize, Ordering::SeqCst);
+    let _base = lock_heap_base(space.as_ptr() as usize);
     unsafe {
         heap.add_to_heap(space.as_ptr() as usize, space.as_ptr().add(100) as usize);
     }
@@ -76,7 +91,7 @@ fn test_heap_alloc_and_free() {
     assert!(heap.alloc_(Layout::from_size_align(1, 1).unwrap()).is_err());
 
     let space: [usize; 100] = [0; 100];
-    HEAP_BASE.store(space.as_ptr() as usize, Ordering::SeqCst);
+    let _base = lock_heap_base(space.as_ptr() as usize);
     unsafe {
         heap.add_to_heap(space.as_ptr() as usize, space.as_ptr().add(100) as usize);
     }
@@ -87,6 +102,14 @@ fn test_heap_alloc_and_free() {
 }
 
 #[test]
+#[cfg_attr(
+    feature = "alloc-bitmap",
+    ignore = "分配位图占用了区域开头的空间，16 字节的区域中没有可分配的块"
+)]
+#[cfg_attr(
+    all(feature = "red-zones", not(feature = "alloc-bitmap")),
+    ignore = "加上红区后需要的块超过了最大的块"
+)]
 fn test_heap_merge_final_order() {
     const NUM_ORDERS: usize = 5;
 
@@ -103,7 +126,7 @@ fn test_heap_merge_final_order() {
     let middle = unsafe { backing_allocation.add(backing_size / 2) } as usize;
     let end = unsafe { backing_allocation.add(backing_size) } as usize;
 
-    HEAP_BASE.store(start, Ordering::SeqCst);
+    let _base = lock_heap_base(start);
     // add two contiguous ranges of memory
     unsafe { heap.add_to_heap(start, middle) };
     unsafe { heap.add_to_heap(middle, end) };
@@ -118,45 +141,58 @@ fn test_heap_merge_final_order() {
     heap.dealloc_(alloc, layout);
 }
 
+#[cfg(all(feature = "debug-checks", not(feature = "alloc-bitmap")))]
+#[test]
+#[should_panic(expected = "out of bounds")]
+fn test_heap_node_bounds() {
+    let heap = LockFreeHeap::<32>::new();
+    let space: [usize; 100] = [0; 100];
+    let outside: [usize; 2] = [0; 2];
+    let _base = lock_heap_base(space.as_ptr() as usize);
+    unsafe {
+        heap.add_to_heap(space.as_ptr() as usize, space.as_ptr().add(100) as usize);
+    }
+    // 链表节点的范围由 add_to_heap 加入的区域决定，释放区域外的指针会被检测出来
+    let ptr = core::ptr::NonNull::from(&outside).cast::<u8>();
+    heap.dealloc_(ptr, Layout::from_size_align(16, 1).unwrap());
+}
+
+#[cfg(all(feature = "debug-checks", not(feature = "alloc-bitmap")))]
+#[test]
+#[should_panic(expected = "out of bounds")]
+fn test_heap_node_bounds_gap() {
+    let heap = LockFreeHeap::<32>::new();
+    let space: [usize; 96] = [0; 96];
+    let base = space.as_ptr() as usize;
+    let _base = lock_heap_base(base);
+    // 两个不相邻的区域，中间的空隙不属于堆
+    unsafe {
+        heap.add_to_heap(base, base + 256);
+        heap.add_to_heap(base + 512, base + 768);
+    }
+    let ptr = core::ptr::NonNull::new((base + 256) as *mut u8).unwrap();
+    heap.dealloc_(ptr, Layout::from_size_align(16, 1).unwrap());
+}
+
 const SMALL_SIZE: usize = 8;
 const LARGE_SIZE: usize = 1024 * 1024; // 1M
 const ALIGN: usize = 8;
 const ORDER: usize = 33;
-const MACHINE_ALIGN: usize = core::mem::size_of::<usize>();
 /// for now 128M is needed
 /// TODO: reduce memory use
 const KERNEL_HEAP_SIZE: usize = 128 * 1024 * 1024;
-const HEAP_BLOCK: usize = KERNEL_HEAP_SIZE / MACHINE_ALIGN;
-#[repr(C, align(0x1000))]
-struct HeapSpace(pub(crate) [usize; HEAP_BLOCK]);
-static mut HEAP: HeapSpace = HeapSpace([0; HEAP_BLOCK]);
 
-// #[global_allocator]
-static HEAP_ALLOCATOR: LockFreeHeap<ORDER> = LockFreeHeap::<ORDER>::new();
-
-/// Init heap
-///
-/// We need `ctor` here because benchmark is running behind the std enviroment,
-/// which means std will do some initialization before execute `fn main()`.
-/// However, our memory allocator must be init in runtime(use linkedlist, which
-/// can not be evaluated in compile time). And in the initialization phase, heap
-/// memory is needed.
-///
-/// So the solution in this dilemma is to run `fn init_heap()` in initialization phase
-/// rather than in `fn main()`. We need `ctor` to do this.
-#[ctor]
-fn init_heap() {
-    let heap_start = &raw mut HEAP as *mut _ as usize;
-    unsafe {
-        HEAP_ALLOCATOR.init(heap_start, HEAP_BLOCK * MACHINE_ALIGN);
-    }
+// 第一次分配时才将静态内存加入堆，因此作为全局分配器时不需要在 main 之前初始化
+crate::static_heap! {
+    // #[global_allocator]
+    static HEAP_ALLOCATOR: StaticHeap<ORDER> = KERNEL_HEAP_SIZE;
 }
 
-// 运行该测试时，需要注释#[global_allocator]和#[ctor]两个注解
+// 运行该测试时，需要注释#[global_allocator]注解
 // PASSED
 #[test]
 fn test_singlethread() {
-    init_heap();
+    let _base = lock_heap_base(0);
     unsafe {
         println!("{:?}", HEAP_ALLOCATOR);
         let small_layout = Layout::from_size_align_unchecked(SMALL_SIZE, ALIGN);
@@ -176,11 +212,11 @@ fn test_singlethread() {
     }
 }
 
-// 运行该测试时，需要注释#[global_allocator]和#[ctor]两个注解
+// 运行该测试时，需要注释#[global_allocator]注解
 // FAILED
 #[test]
 fn test_multithread() {
-    init_heap();
+    let _base = lock_heap_base(0);
     println!("{:?}", HEAP_ALLOCATOR);
     let mut handles: Vec<JoinHandle<()>> = Vec::new();
     for _ in 0..100 {
@@ -207,10 +243,11 @@ fn test_multithread() {
     println!("{:?}", HEAP_ALLOCATOR);
 }
 
-// 运行该测试时，需要取消注释#[global_allocator]和#[ctor]两个注解
+// 运行该测试时，需要取消注释#[global_allocator]注解
 // PASSED
 #[test]
 fn test_global_allocator_singlethread() {
+    let _base = lock_heap_base(0);
     println!("{:?}", HEAP_ALLOCATOR);
     let small: Box<usize> = Box::new(42);
     println!("{:?}", HEAP_ALLOCATOR);
@@ -221,10 +258,11 @@ fn test_global_allocator_singlethread() {
     assert!((*large)[42] == 0);
 }
 
-// 运行该测试时，需要取消注释#[global_allocator]和#[ctor]两个注解
+// 运行该测试时，需要取消注释#[global_allocator]注解
 // FAILED
 #[test]
 fn test_global_allocator_multithread() {
+    let _base = lock_heap_base(0);
     let mut handles: Vec<JoinHandle<()>> = Vec::new();
     for _ in 0..100 {
         handles.push(spawn(|| {
@@ -242,3 +280,1286 @@ fn test_global_allocator_multithread() {
     }
     println!("{:?}", HEAP_ALLOCATOR);
 }
+
+#[cfg(any(feature = "alloc-bitmap", feature = "poison"))]
+std::thread_local! {
+    /// 当前线程最近一次报告的堆错误
+    static LAST_HEAP_ERROR: core::cell::Cell<Option<crate::HeapError>> = const { core::cell::Cell::new(None) };
+}
+
+#[cfg(any(feature = "alloc-bitmap", feature = "poison"))]
+struct HeapErrorHandlerImpl;
+
+#[cfg(any(feature = "alloc-bitmap", feature = "poison"))]
+#[crate_interface::impl_interface]
+impl crate::HeapErrorHandler for HeapErrorHandlerImpl {
+    fn handle_heap_error(err: crate::HeapError) {
+        LAST_HEAP_ERROR.with(|last| last.set(Some(err)));
+    }
+}
+
+#[cfg(feature = "alloc-bitmap")]
+#[test]
+#[cfg_attr(feature = "red-zones", ignore = "红区改变了块的大小")]
+fn test_heap_invalid_free() {
+    use crate::HeapError;
+
+    let heap = LockFreeHeap::<32>::new();
+    let space: [usize; 100] = [0; 100];
+    let _base = lock_heap_base(space.as_ptr() as usize);
+    unsafe {
+        heap.add_to_heap(space.as_ptr() as usize, space.as_ptr().add(100) as usize);
+    }
+    let layout = Layout::from_size_align(16, 1).unwrap();
+    let addr = heap.alloc_(layout).unwrap();
+    let allocated = heap.stats_alloc_actual();
+
+    // layout 不一致
+    heap.dealloc_(addr, Layout::from_size_align(32, 1).unwrap());
+    assert_eq!(
+        LAST_HEAP_ERROR.take(),
+        Some(HeapError::LayoutMismatch {
+            addr: addr.as_ptr() as usize,
+            alloc_order: 4,
+            free_order: 5,
+        })
+    );
+    assert_eq!(heap.stats_alloc_actual(), allocated);
+
+    // 释放不是由 alloc_ 返回的指针
+    let inner = unsafe { addr.add(8) };
+    heap.dealloc_(inner, layout);
+    assert_eq!(
+        LAST_HEAP_ERROR.take(),
+        Some(HeapError::InvalidFree {
+            addr: inner.as_ptr() as usize
+        })
+    );
+    let outside = core::ptr::NonNull::from(&HEAP_BASE).cast::<u8>();
+    heap.dealloc_(outside, layout);
+    assert_eq!(
+        LAST_HEAP_ERROR.take(),
+        Some(HeapError::InvalidFree {
+            addr: outside.as_ptr() as usize
+        })
+    );
+
+    // 正常释放后重复释放
+    heap.dealloc_(addr, layout);
+    assert_eq!(LAST_HEAP_ERROR.take(), None);
+    heap.dealloc_(addr, layout);
+    assert_eq!(
+        LAST_HEAP_ERROR.take(),
+        Some(HeapError::DoubleFree {
+            addr: addr.as_ptr() as usize,
+            order: 4,
+        })
+    );
+    assert_eq!(heap.stats_alloc_actual(), 0);
+
+    // 重复释放没有破坏空闲链表，同一个块只能被分配一次
+    let a = heap.alloc_(layout).unwrap();
+    let b = heap.alloc_(layout).unwrap();
+    assert_ne!(a, b);
+}
+
+#[cfg(feature = "alloc-hooks")]
+std::thread_local! {
+    /// 当前线程记录的堆事件，为 None 时不记录
+    static HEAP_EVENTS: core::cell::RefCell<Option<Vec<crate::HeapEvent>>> = const { core::cell::RefCell::new(None) };
+}
+
+#[cfg(feature = "alloc-hooks")]
+struct AllocHookImpl;
+
+#[cfg(feature = "alloc-hooks")]
+#[crate_interface::impl_interface]
+impl crate::AllocHook for AllocHookImpl {
+    fn on_heap_event(event: crate::HeapEvent) {
+        HEAP_EVENTS.with(|events| {
+            if let Some(events) = events.borrow_mut().as_mut() {
+                events.push(event);
+            }
+        });
+    }
+}
+
+#[cfg(all(
+    feature = "alloc-hooks",
+    not(any(feature = "alloc-bitmap", feature = "red-zones"))
+))]
+#[test]
+fn test_heap_events() {
+    use crate::HeapEvent;
+
+    #[repr(align(64))]
+    struct Space([u8; 64]);
+    let space = Space([0; 64]);
+    let start = space.0.as_ptr() as usize;
+    let heap = LockFreeHeap::<8>::new();
+    let _base = lock_heap_base(start);
+    unsafe { heap.add_to_heap(start, start + 64) };
+
+    HEAP_EVENTS.set(Some(Vec::new()));
+    let layout = Layout::from_size_align(16, 1).unwrap();
+    let addr = heap.alloc_with_tag(layout, 42).unwrap();
+    let whole = Layout::from_size_align(64, 1).unwrap();
+    assert!(heap.alloc_with_tag(whole, 7).is_none());
+    heap.dealloc_with_tag(addr, layout, 42);
+    let events = HEAP_EVENTS.take().unwrap();
+
+    let addr = addr.as_ptr() as usize;
+    assert_eq!(addr, start);
+    assert_eq!(
+        events,
+        [
+            HeapEvent::Split { addr, order: 6 },
+            HeapEvent::Split { addr, order: 5 },
+            HeapEvent::Alloc {
+                addr,
+                layout,
+                order: 4,
+                tag: 42
+            },
+            HeapEvent::OutOfMemory {
+                layout: whole,
+                tag: 7
+            },
+            HeapEvent::Free {
+                addr,
+                layout,
+                order: 4,
+                tag: 42
+            },
+            HeapEvent::Merge { addr, order: 5 },
+            HeapEvent::Merge { addr, order: 6 },
+        ]
+    );
+}
+
+#[cfg(feature = "leak-registry")]
+#[test]
+fn test_heap_leaks() {
+    let heap = LockFreeHeap::<32>::new();
+    let space: [usize; 100] = [0; 100];
+    let mut registry: [usize; 32] = [0; 32];
+    let _base = lock_heap_base(space.as_ptr() as usize);
+    unsafe {
+        heap.init_leak_registry(registry.as_mut_ptr() as usize, size_of_val(&registry));
+        heap.add_to_heap(space.as_ptr() as usize, space.as_ptr().add(100) as usize);
+    }
+    assert_eq!(heap.leaks().count(), 0);
+
+    let layouts = [
+        Layout::from_size_align(16, 1).unwrap(),
+        Layout::from_size_align(24, 8).unwrap(),
+        Layout::from_size_align(64, 16).unwrap(),
+    ];
+    let addrs: Vec<_> = layouts
+        .iter()
+        .enumerate()
+        .map(|(tag, &layout)| heap.alloc_with_tag(layout, tag).unwrap())
+        .collect();
+    heap.dealloc_(addrs[1], layouts[1]);
+
+    let mut leaks: Vec<_> = heap.leaks().collect();
+    leaks.sort_by_key(|leak| leak.tag);
+    assert_eq!(
+        leaks,
+        [0, 2].map(|i| crate::Allocation {
+            addr: addrs[i].as_ptr() as usize,
+            layout: layouts[i],
+            tag: i,
+        })
+    );
+
+    heap.dealloc_(addrs[0], layouts[0]);
+    heap.dealloc_(addrs[2], layouts[2]);
+    assert_eq!(heap.leaks().count(), 0);
+    assert_eq!(heap.leak_registry_overflow(), 0);
+}
+
+#[cfg(feature = "leak-registry")]
+#[test]
+fn test_leak_registry_churn() {
+    use crate::leak_registry::LeakRegistry;
+
+    const LIVE: usize = 4;
+    let mut table: [usize; 256] = [0; 256];
+    let _base = lock_heap_base(table.as_ptr() as usize);
+    let registry = LeakRegistry::new();
+    unsafe { registry.init(table.as_mut_ptr() as usize, size_of_val(&table)) };
+    let layout = Layout::from_size_align(16, 16).unwrap();
+
+    // 反复登记和注销不同的地址，表中的表项最终几乎都被释放过
+    for round in 0..10000 {
+        let addrs: Vec<_> = (0..LIVE).map(|i| (round * LIVE + i) * 16).collect();
+        for &addr in &addrs {
+            registry.insert(addr, layout, round);
+        }
+        assert_eq!(registry.iter().count(), LIVE);
+        for &addr in &addrs {
+            registry.remove(addr);
+        }
+        assert_eq!(registry.iter().count(), 0);
+    }
+    // 同时登记的块不超过 LIVE 个，查找的距离不随释放的次数增长
+    assert!(registry.max_probe() < LIVE);
+    // 没有登记的地址也只探测有限的表项
+    registry.remove(usize::MAX & !15);
+    // 释放的表项全部可以重新使用
+    let capacity = size_of_val(&table) / (4 * size_of::<usize>());
+    for i in 0..capacity {
+        registry.insert(i * 16, layout, 0);
+    }
+    assert_eq!(registry.iter().count(), capacity);
+    assert_eq!(registry.overflow(), 0);
+    for i in 0..capacity {
+        registry.remove(i * 16);
+    }
+    assert_eq!(registry.iter().count(), 0);
+}
+
+#[test]
+fn test_heap_dump() {
+    use crate::{DumpError, HeapDump};
+
+    let heap = LockFreeHeap::<8>::new();
+    let space: [usize; 100] = [0; 100];
+    let start = space.as_ptr() as usize;
+    let _base = lock_heap_base(start);
+    unsafe { heap.add_to_heap(start, start + size_of_val(&space)) };
+    let layout = Layout::from_size_align(16, 1).unwrap();
+    let addr = heap.alloc_(layout).unwrap();
+
+    let mut buf = [0u8; 1024];
+    let needed = match heap.dump(&mut buf[..16]) {
+        Err(DumpError::BufferTooSmall { needed }) => needed,
+        result => panic!("unexpected {:?}", result),
+    };
+    assert_eq!(heap.dump(&mut buf), Ok(needed));
+    let dump = HeapDump::parse(&buf[..needed]).unwrap();
+    assert_eq!(dump.order(), 8);
+    assert_eq!(dump.stats().allocated, heap.stats_alloc_actual());
+    assert_eq!(dump.stats().total, heap.stats_total_bytes());
+    assert_eq!(dump.regions().count(), 1);
+
+    // 空闲块与已分配的块恰好覆盖整个区域
+    let free: usize = (0..8)
+        .map(|order| dump.free_blocks(order).count() << order)
+        .sum();
+    assert_eq!(free + dump.stats().allocated, dump.stats().total);
+    let allocated = addr.as_ptr() as usize - start;
+    assert!((0..8).all(|order| dump.free_blocks(order).all(|block| block != allocated)));
+
+    let mut text = String::new();
+    heap.dump_text(&mut text).unwrap();
+    assert_eq!(text, dump.to_string());
+
+    assert_eq!(
+        HeapDump::parse(&buf[..needed - 1]).unwrap_err(),
+        DumpError::Invalid
+    );
+}
+
+#[test]
+fn test_heap_dump_invalid() {
+    use crate::{DumpError, HeapDump};
+
+    let heap = LockFreeHeap::<8>::new();
+    let space: [usize; 64] = [0; 64];
+    let start = space.as_ptr() as usize;
+    let _base = lock_heap_base(start);
+    unsafe {
+        heap.add_to_heap(start, start + 256);
+        heap.add_to_heap(start + 256, start + 512);
+    }
+    let mut buf = [0u8; 1024];
+    let len = heap.dump(&mut buf).unwrap();
+    let dump = HeapDump::parse(&buf[..len]).unwrap();
+    assert_eq!(dump.free_blocks(8).count(), 0);
+    assert_eq!(dump.free_blocks(usize::MAX).count(), 0);
+
+    // 依次修改级数和两个区域的字段
+    let modified = |pos: usize, value: u64| {
+        let mut data = buf[..len].to_vec();
+        data[pos..pos + 8].copy_from_slice(&value.to_le_bytes());
+        HeapDump::parse(&data).map(|_| ())
+    };
+    assert_eq!(modified(24, 70), Err(DumpError::Invalid));
+    assert_eq!(modified(56, 1000), Err(DumpError::Invalid));
+    // 第一个区域的 data 大于 end
+    assert_eq!(modified(72, 300), Err(DumpError::Invalid));
+    // 第二个区域的 start 落在第一个区域中
+    assert_eq!(modified(88, 128), Err(DumpError::Invalid));
+    assert_eq!(modified(88, 256), Ok(()));
+}
+
+#[cfg(not(any(feature = "alloc-bitmap", feature = "red-zones")))]
+#[test]
+fn test_heap_render() {
+    use crate::HeapDump;
+
+    #[repr(align(512))]
+    struct Space([u8; 512]);
+    let space = Space([0; 512]);
+    let start = space.0.as_ptr() as usize;
+    let heap = LockFreeHeap::<10>::new();
+    let _base = lock_heap_base(start);
+    unsafe { heap.add_to_heap(start, start + 512) };
+    let layout = Layout::from_size_align(16, 1).unwrap();
+    heap.alloc_(layout).unwrap();
+
+    let mut buf = [0u8; 1024];
+    let len = heap.dump(&mut buf).unwrap();
+    let dump = HeapDump::parse(&buf[..len]).unwrap();
+    let mut map = String::new();
+    dump.render_ascii(&mut map, 16).unwrap();
+    assert_eq!(
+        map,
+        "region [0x0, 0x200) 512 bytes\n\
+         \x20 free |:...............|\n\
+         \x20    8 |        --------|\n\
+         \x20    7 |    ----        |\n\
+         \x20    6 |  --            |\n\
+         \x20    5 | -              |\n\
+         \x20    4 |-               |\n"
+    );
+
+    let mut svg = String::new();
+    dump.render_svg(&mut svg).unwrap();
+    assert!(svg.starts_with("<svg ") && svg.ends_with("</svg>\n"));
+    // free 一行中每个空闲块一个矩形，各级链表中每个空闲块再各有一个
+    assert_eq!(svg.matches("<title>").count(), 10);
+
+    // 手工构造的转储中，区域和空闲块都位于地址空间的末尾，计算结束位置时不能溢出
+    let top = u64::MAX - 63;
+    let words: Vec<u64> = [0, 7, 0, 0, 48, 1, top, top, u64::MAX - 15]
+        .into_iter()
+        .chain((0..6).map(|_| 0))
+        .chain([1, top])
+        .collect();
+    let mut data = b"PILFDUMP".to_vec();
+    data.extend_from_slice(&1u64.to_le_bytes());
+    data.extend(words.iter().flat_map(|word| word.to_le_bytes()));
+    let dump = HeapDump::parse(&data).unwrap();
+    let mut map = String::new();
+    dump.render_ascii(&mut map, 16).unwrap();
+    assert!(map.contains("48 bytes"));
+    let mut svg = String::new();
+    dump.render_svg(&mut svg).unwrap();
+    assert_eq!(svg.matches("<title>").count(), 2);
+}
+
+#[test]
+fn test_heap_verify() {
+    use crate::{HeapDump, Violation};
+
+    let heap = LockFreeHeap::<8>::new();
+    let space: [usize; 100] = [0; 100];
+    let start = space.as_ptr() as usize;
+    let _base = lock_heap_base(start);
+    // 区域的起止地址都不对齐
+    unsafe { heap.add_to_heap(start + 3, start + size_of_val(&space) - 5) };
+    let layout = Layout::from_size_align(24, 8).unwrap();
+    let addr1 = heap.alloc_(layout).unwrap();
+    let addr2 = heap.alloc_(layout).unwrap();
+    heap.dealloc_(addr1, layout);
+
+    let mut buf = [0u8; 1024];
+    let len = heap.dump(&mut buf).unwrap();
+    assert_eq!(HeapDump::parse(&buf[..len]).unwrap().verify(), Ok(()));
+
+    // 将一个空闲块移动 8 字节，空闲链表在转储的末尾，因此从后向前查找
+    let dump = HeapDump::parse(&buf[..len]).unwrap();
+    let (order, block) = (0..8)
+        .find_map(|order| Some((order, dump.free_blocks(order).next()?)))
+        .unwrap();
+    let pos = buf[..len]
+        .rchunks_exact(8)
+        .position(|word| word == (block as u64).to_le_bytes())
+        .map(|i| len - (i + 1) * 8)
+        .unwrap();
+    buf[pos..pos + 8].copy_from_slice(&(block as u64 + 8).to_le_bytes());
+    let mut violations = Vec::new();
+    HeapDump::parse(&buf[..len])
+        .unwrap()
+        .violations(|violation| violations.push(violation));
+    assert!(violations.contains(&Violation::Misaligned {
+        block: block + 8,
+        order
+    }));
+
+    heap.dealloc_(addr2, layout);
+
+    // 手工构造的转储中块的大小与统计信息接近 usize::MAX，检查时不能溢出
+    let words: Vec<u64> = [0, 64, 0, u64::MAX, 16, 1, 0, 0, 16]
+        .into_iter()
+        .chain((0..63).map(|_| 0))
+        .chain([2, 0, 1 << 63])
+        .collect();
+    let mut data = b"PILFDUMP".to_vec();
+    data.extend_from_slice(&1u64.to_le_bytes());
+    data.extend(words.iter().flat_map(|word| word.to_le_bytes()));
+    let mut violations = Vec::new();
+    HeapDump::parse(&data)
+        .unwrap()
+        .violations(|violation| violations.push(violation));
+    assert!(violations.contains(&Violation::OutsideRegions {
+        block: 0,
+        order: 63
+    }));
+    assert!(violations.contains(&Violation::Accounting {
+        free: usize::MAX,
+        allocated: usize::MAX,
+        total: 16
+    }));
+}
+
+#[cfg(not(any(feature = "alloc-bitmap", feature = "red-zones")))]
+#[test]
+fn test_heap_align() {
+    let backing = Layout::from_size_align(16384, 16384).unwrap();
+    let base = unsafe { std::alloc::alloc(backing) } as usize;
+    let _base = lock_heap_base(base);
+    // 区域中没有按 16 KiB 对齐的地址
+    let (start, end) = (base + 4096, base + 12288);
+    let page = Layout::from_size_align(16, 4096).unwrap();
+
+    // 只占用 16 字节，其余部分切分后放回空闲链表
+    let heap = LockFreeHeap::<16>::new();
+    unsafe { heap.add_to_heap(start, end) };
+    let addr1 = heap.alloc_(page).unwrap();
+    assert!((addr1.as_ptr() as usize).is_multiple_of(4096));
+    assert_eq!(heap.stats_alloc_actual(), 16);
+    let addr2 = heap.alloc_(page).unwrap();
+    assert!((addr2.as_ptr() as usize).is_multiple_of(4096));
+    assert_ne!(addr1, addr2);
+    // 剩余的空闲块都不是按 4 KiB 对齐的，但仍然可以分配
+    assert!(heap.alloc_(page).is_err());
+    assert!(heap
+        .alloc_(Layout::from_size_align(2048, 1).unwrap())
+        .is_ok());
+    heap.dealloc_(addr1, page);
+    // 释放后合并回 4 KiB 的块
+    assert_eq!(heap.alloc_(page).unwrap(), addr1);
+
+    // 对齐要求大于最大块时，使用起始地址恰好对齐的块
+    let heap = LockFreeHeap::<8>::new();
+    unsafe { heap.add_to_heap(start, end) };
+    let addr = heap.alloc_(page).unwrap();
+    assert!((addr.as_ptr() as usize).is_multiple_of(4096));
+    assert_eq!(heap.stats_alloc_actual(), 16);
+    heap.dealloc_(addr, page);
+    assert!(heap
+        .alloc_(Layout::from_size_align(16, 16384).unwrap())
+        .is_err());
+
+    unsafe { std::alloc::dealloc(base as *mut u8, backing) };
+}
+
+#[cfg(feature = "poison")]
+#[test]
+fn test_heap_poison() {
+    use crate::HeapError;
+
+    let heap = LockFreeHeap::<32>::new();
+    let space: [usize; 100] = [0; 100];
+    let _base = lock_heap_base(space.as_ptr() as usize);
+    unsafe {
+        heap.add_to_heap(space.as_ptr() as usize, space.as_ptr().add(100) as usize);
+    }
+
+    // 释放后写入，再次分配到同一个块时报告
+    let layout = Layout::from_size_align(64, 64).unwrap();
+    let addr = heap.alloc_(layout).unwrap();
+    assert_eq!(LAST_HEAP_ERROR.take(), None);
+    heap.dealloc_(addr, layout);
+    unsafe { addr.as_ptr().add(40).write(0) };
+    assert_eq!(heap.alloc_(layout), Ok(addr));
+    assert_eq!(
+        LAST_HEAP_ERROR.take(),
+        Some(HeapError::UseAfterFree {
+            addr: addr.as_ptr() as usize,
+            offset: 40
+        })
+    );
+
+    // 写入超出分配的大小，释放时报告；块仍然正常释放
+    let small = Layout::from_size_align(20, 1).unwrap();
+    let block = heap.alloc_(small).unwrap();
+    unsafe { block.as_ptr().add(19).write(0) };
+    heap.dealloc_(block, small);
+    assert_eq!(LAST_HEAP_ERROR.take(), None);
+    let block = heap.alloc_(small).unwrap();
+    unsafe { block.as_ptr().add(20).write(0) };
+    heap.dealloc_(block, small);
+    assert_eq!(
+        LAST_HEAP_ERROR.take(),
+        Some(HeapError::RedZoneOverflow {
+            addr: block.as_ptr() as usize,
+            offset: 20
+        })
+    );
+
+    heap.dealloc_(addr, layout);
+    assert_eq!(heap.stats_alloc_actual(), 0);
+    assert_eq!(LAST_HEAP_ERROR.take(), None);
+}
+
+#[cfg(feature = "node-canary")]
+#[test]
+#[should_panic(expected = "list node canary corrupted at block")]
+fn test_heap_node_canary() {
+    let heap = LockFreeHeap::<32>::new();
+    let space: [usize; 100] = [0; 100];
+    let _base = lock_heap_base(space.as_ptr() as usize);
+    unsafe {
+        heap.add_to_heap(space.as_ptr() as usize, space.as_ptr().add(100) as usize);
+    }
+    let layout = Layout::from_size_align(64, 64).unwrap();
+    let addr = heap.alloc_(layout).unwrap();
+    heap.dealloc_(addr, layout);
+
+    // 释放后写入覆盖了链表节点，之后的遍历会发现
+    unsafe { core::ptr::write_bytes(addr.as_ptr(), 0, 64) };
+    let _ = heap.alloc_(layout);
+}
+
+#[cfg(feature = "node-canary")]
+#[test]
+#[should_panic(expected = "list node pointer corrupted at block")]
+fn test_heap_node_canary_pointer() {
+    let heap = LockFreeHeap::<32>::new();
+    let space: [usize; 100] = [0; 100];
+    let _base = lock_heap_base(space.as_ptr() as usize);
+    unsafe {
+        heap.add_to_heap(space.as_ptr() as usize, space.as_ptr().add(100) as usize);
+    }
+    let layout = Layout::from_size_align(64, 64).unwrap();
+    let addr = heap.alloc_(layout).unwrap();
+    heap.dealloc_(addr, layout);
+
+    // 只覆盖节点的指针字，引用计数字中的校验值不变
+    unsafe { addr.cast::<usize>().write(pi_pointer::NULL_PTR) };
+    let _ = heap.alloc_(layout);
+}
+
+#[cfg(feature = "heap-grow")]
+std::thread_local! {
+    /// 当前线程扩展堆时依次返回的区域，为空时无法扩展
+    static GROW_REGIONS: core::cell::RefCell<Vec<(usize, usize)>> = const { core::cell::RefCell::new(Vec::new()) };
+}
+
+/// 正在扩展堆的线程数，以及同时扩展堆的最大线程数
+#[cfg(feature = "heap-grow")]
+static GROWING: AtomicUsize = AtomicUsize::new(0);
+#[cfg(feature = "heap-grow")]
+static MAX_GROWING: AtomicUsize = AtomicUsize::new(0);
+
+#[cfg(feature = "heap-grow")]
+struct HeapGrowImpl;
+
+#[cfg(feature = "heap-grow")]
+#[crate_interface::impl_interface]
+impl crate::HeapGrow for HeapGrowImpl {
+    fn grow_heap(_layout: Layout) -> Option<(usize, usize)> {
+        let region = GROW_REGIONS.with_borrow_mut(|regions| regions.pop())?;
+        let growing = GROWING.fetch_add(1, Ordering::SeqCst) + 1;
+        MAX_GROWING.fetch_max(growing, Ordering::SeqCst);
+        // 让其它线程有机会在扩展的过程中发现内存不足
+        std::thread::sleep(std::time::Duration::from_millis(1));
+        GROWING.fetch_sub(1, Ordering::SeqCst);
+        Some(region)
+    }
+}
+
+#[cfg(feature = "heap-grow")]
+#[test]
+fn test_heap_grow() {
+    const CHUNK: usize = 4096;
+    const NUM_THREADS: usize = 4;
+    // 扩展后的内存可能被其它线程取走，每个线程最多需要扩展 NUM_THREADS 次
+    const NUM_CHUNKS: usize = 1 + NUM_THREADS * NUM_THREADS;
+
+    let backing = Layout::from_size_align(CHUNK * NUM_CHUNKS, CHUNK).unwrap();
+    let base = unsafe { std::alloc::alloc(backing) } as usize;
+    let _base = lock_heap_base(base);
+    let chunk = |i: usize| (base + i * CHUNK, base + (i + 1) * CHUNK);
+    let layout = Layout::from_size_align(1024, 1).unwrap();
+
+    // 空堆在第一次分配时扩展
+    let heap = LockFreeHeap::<16>::new();
+    GROW_REGIONS.with_borrow_mut(|regions| regions.push(chunk(0)));
+    let addr = heap.alloc_(layout).unwrap();
+    assert!(GROW_REGIONS.with_borrow(|regions| regions.is_empty()));
+    // 无法扩展时返回错误
+    assert!(heap
+        .alloc_(Layout::from_size_align(CHUNK, 1).unwrap())
+        .is_err());
+    heap.dealloc_(addr, layout);
+
+    // 多个线程同时内存不足时，只有一个线程扩展，其余线程等待后重试
+    let heap = LockFreeHeap::<16>::new();
+    MAX_GROWING.store(0, Ordering::SeqCst);
+    let barrier = std::sync::Barrier::new(NUM_THREADS);
+    let mut addrs: Vec<usize> = std::thread::scope(|s| {
+        let handles: Vec<_> = (0..NUM_THREADS)
+            .map(|thread| {
+                let (heap, barrier) = (&heap, &barrier);
+                s.spawn(move || {
+                    GROW_REGIONS.with_borrow_mut(|regions| {
+                        regions
+                            .extend((0..NUM_THREADS).map(|i| chunk(1 + thread * NUM_THREADS + i)))
+                    });
+                    barrier.wait();
+                    heap.alloc_(layout).unwrap().as_ptr() as usize
+                })
+            })
+            .collect();
+        handles.into_iter().map(|h| h.join().unwrap()).collect()
+    });
+    assert_eq!(MAX_GROWING.load(Ordering::SeqCst), 1);
+    addrs.sort();
+    addrs.dedup();
+    assert_eq!(addrs.len(), NUM_THREADS);
+
+    unsafe { std::alloc::dealloc(base as *mut u8, backing) };
+}
+
+/// 区域表已满时 add_to_heap panic，而不是加入区域表中没有记录的内存
+#[test]
+#[should_panic(expected = "too many heap regions")]
+fn test_heap_region_limit() {
+    use crate::region::MAX_REGIONS;
+
+    const CHUNK: usize = 256;
+
+    #[repr(align(256))]
+    struct Space([u8; CHUNK * (MAX_REGIONS + 1)]);
+    let space = Space([0; CHUNK * (MAX_REGIONS + 1)]);
+    let start = space.0.as_ptr() as usize;
+    let _base = lock_heap_base(start);
+    let heap = LockFreeHeap::<16>::new();
+    for i in 0..MAX_REGIONS {
+        unsafe { heap.add_to_heap(start + i * CHUNK, start + (i + 1) * CHUNK) };
+    }
+    assert!(heap.owns(start + (MAX_REGIONS - 1) * CHUNK + CHUNK - 1));
+    unsafe {
+        heap.add_to_heap(
+            start + MAX_REGIONS * CHUNK,
+            start + (MAX_REGIONS + 1) * CHUNK,
+        )
+    };
+}
+
+#[cfg(feature = "heap-grow")]
+#[test]
+fn test_heap_grow_region_limit() {
+    use crate::region::MAX_REGIONS;
+    use crate::HeapDump;
+
+    const CHUNK: usize = 256;
+    const NUM_CHUNKS: usize = MAX_REGIONS + 4;
+
+    let backing = Layout::from_size_align(CHUNK * NUM_CHUNKS, CHUNK).unwrap();
+    let base = unsafe { std::alloc::alloc(backing) } as usize;
+    let _base = lock_heap_base(base);
+    // 每次扩展得到的区域都足够分配一个块
+    let layout = Layout::from_size_align(CHUNK / 4, 1).unwrap();
+
+    let heap = LockFreeHeap::<16>::new();
+    GROW_REGIONS.with_borrow_mut(|regions| {
+        regions.extend((0..NUM_CHUNKS).map(|i| (base + i * CHUNK, base + (i + 1) * CHUNK)))
+    });
+    let mut addrs = Vec::new();
+    while let Ok(addr) = heap.alloc_(layout) {
+        addrs.push(addr);
+    }
+    // 区域表满后不再扩展，分配失败而不是 panic
+    assert_eq!(
+        GROW_REGIONS.with_borrow(|regions| regions.len()),
+        NUM_CHUNKS - MAX_REGIONS
+    );
+
+    // 堆中的内存都记录在区域表中
+    let mut buf = vec![0u8; 4096];
+    let len = heap.dump(&mut buf).unwrap();
+    let dump = HeapDump::parse(&buf[..len]).unwrap();
+    assert_eq!(dump.regions().count(), MAX_REGIONS);
+    assert_eq!(
+        dump.regions()
+            .map(|region| region.end - region.data)
+            .sum::<usize>(),
+        heap.stats_total_bytes()
+    );
+    assert_eq!(dump.verify(), Ok(()));
+
+    for addr in addrs {
+        heap.dealloc_(addr, layout);
+    }
+    GROW_REGIONS.with_borrow_mut(|regions| regions.clear());
+    unsafe { std::alloc::dealloc(base as *mut u8, backing) };
+}
+
+#[cfg(feature = "heap-release")]
+std::thread_local! {
+    /// 当前线程中 HeapRelease 的调用记录
+    static RELEASE_CALLS: core::cell::RefCell<Vec<(&'static str, usize, usize)>> = const { core::cell::RefCell::new(Vec::new()) };
+}
+
+#[cfg(feature = "heap-release")]
+struct HeapReleaseImpl;
+
+#[cfg(feature = "heap-release")]
+#[crate_interface::impl_interface]
+impl crate::HeapRelease for HeapReleaseImpl {
+    fn release_memory(addr: usize, size: usize) {
+        // 模拟 madvise(MADV_DONTNEED) 之后内容清零
+        unsafe { core::ptr::write_bytes(addr as *mut u8, 0, size) };
+        RELEASE_CALLS.with_borrow_mut(|calls| calls.push(("release", addr, size)));
+    }
+
+    fn reacquire_memory(addr: usize, size: usize) {
+        RELEASE_CALLS.with_borrow_mut(|calls| calls.push(("reacquire", addr, size)));
+    }
+}
+
+#[cfg(all(
+    feature = "heap-release",
+    not(any(feature = "alloc-bitmap", feature = "red-zones"))
+))]
+#[test]
+fn test_heap_release() {
+    let backing = Layout::from_size_align(8192, 8192).unwrap();
+    let base = unsafe { std::alloc::alloc(backing) } as usize;
+    let _base = lock_heap_base(base);
+    // 最大的块为 4 KiB，堆中有两个最大的块
+    let heap = LockFreeHeap::<13>::new();
+    unsafe { heap.add_to_heap(base, base + 8192) };
+    let small = Layout::from_size_align(16, 1).unwrap();
+    let large = Layout::from_size_align(4096, 1).unwrap();
+    let addr1 = heap.alloc_(small).unwrap().as_ptr() as usize;
+    let other = (addr1 & !4095) ^ 4096;
+
+    // 只释放另一个完全空闲的最大块
+    assert_eq!(heap.trim(), 4096);
+    assert_eq!(RELEASE_CALLS.take(), [("release", other, 4096)]);
+    assert_eq!(heap.stats_total_bytes(), 4096);
+
+    // 没有空闲的最大块时取回
+    let addr2 = heap.alloc_(large).unwrap();
+    assert_eq!(addr2.as_ptr() as usize, other);
+    assert_eq!(RELEASE_CALLS.take(), [("reacquire", other, 4096)]);
+    assert_eq!(heap.stats_total_bytes(), 8192);
+
+    // 释放切分剩下的所有空闲块，分配时取回满足要求的最小的块
+    assert_eq!(heap.release_free(0), 4096 - 16);
+    assert_eq!(RELEASE_CALLS.take().len(), 8);
+    let addr3 = heap.alloc_(small).unwrap();
+    assert_eq!(addr3.as_ptr() as usize, addr1 ^ 16);
+    assert_eq!(RELEASE_CALLS.take(), [("reacquire", addr1 ^ 16, 16)]);
+    assert!(heap.alloc_(large).is_err());
+    // 取回的块重新毒化，不会被误报为释放后写入
+    #[cfg(feature = "poison")]
+    assert_eq!(LAST_HEAP_ERROR.take(), None);
+
+    heap.dealloc_(addr2, large);
+    heap.dealloc_(addr3, small);
+    unsafe { std::alloc::dealloc(base as *mut u8, backing) };
+}
+
+/// 释放的块的伙伴被释放时，两者应当合并，合并时取回伙伴的物理内存
+#[cfg(all(
+    feature = "heap-release",
+    not(any(feature = "alloc-bitmap", feature = "red-zones"))
+))]
+#[test]
+fn test_heap_release_merge() {
+    let backing = Layout::from_size_align(4096, 4096).unwrap();
+    let base = unsafe { std::alloc::alloc(backing) } as usize;
+    let _base = lock_heap_base(base);
+    let heap = LockFreeHeap::<13>::new();
+    unsafe { heap.add_to_heap(base, base + 4096) };
+    let small = Layout::from_size_align(16, 1).unwrap();
+    let large = Layout::from_size_align(4096, 1).unwrap();
+    let addr = heap.alloc_(small).unwrap();
+    assert_eq!(addr.as_ptr() as usize, base);
+
+    // 切分剩下的 16 到 2048 字节的块都被释放，release_free 的参数小于 ORDER - 1
+    assert_eq!(heap.release_free(4), 4096 - 16);
+    assert_eq!(RELEASE_CALLS.take().len(), 8);
+    assert_eq!(heap.stats_total_bytes(), 16);
+
+    // 逐级与被释放的伙伴合并，最后得到整个最大的块
+    heap.dealloc_(addr, small);
+    let expected: Vec<_> = (4..12)
+        .map(|order| ("reacquire", base + (1 << order), 1 << order))
+        .collect();
+    assert_eq!(RELEASE_CALLS.take(), expected);
+    assert_eq!(heap.stats_total_bytes(), 4096);
+    let whole = heap.alloc_(large).unwrap();
+    assert_eq!(whole.as_ptr() as usize, base);
+    assert_eq!(RELEASE_CALLS.take(), []);
+    #[cfg(feature = "poison")]
+    assert_eq!(LAST_HEAP_ERROR.take(), None);
+
+    heap.dealloc_(whole, large);
+    unsafe { std::alloc::dealloc(base as *mut u8, backing) };
+}
+
+#[cfg(feature = "watermarks")]
+std::thread_local! {
+    /// 当前线程收到的内存压力通知
+    static PRESSURE_CALLS: core::cell::RefCell<Vec<(crate::Pressure, usize)>> = const { core::cell::RefCell::new(Vec::new()) };
+}
+
+#[cfg(feature = "watermarks")]
+struct MemoryPressureImpl;
+
+#[cfg(feature = "watermarks")]
+#[crate_interface::impl_interface]
+impl crate::MemoryPressure for MemoryPressureImpl {
+    fn on_memory_pressure(pressure: crate::Pressure, free: usize) {
+        PRESSURE_CALLS.with_borrow_mut(|calls| calls.push((pressure, free)));
+    }
+}
+
+#[cfg(all(
+    feature = "watermarks",
+    not(any(feature = "alloc-bitmap", feature = "red-zones"))
+))]
+#[test]
+fn test_heap_watermarks() {
+    use crate::Pressure;
+
+    #[repr(align(4096))]
+    struct Space([u8; 4096]);
+    let space = Space([0; 4096]);
+    let start = space.0.as_ptr() as usize;
+    let _base = lock_heap_base(start);
+    let heap = LockFreeHeap::<13>::new();
+    unsafe { heap.add_to_heap(start, start + 4096) };
+    heap.set_watermarks(1024, 2048);
+    let layout = |size| Layout::from_size_align(size, 1).unwrap();
+
+    let addr1 = heap.alloc_(layout(2048)).unwrap();
+    let addr2 = heap.alloc_(layout(1024)).unwrap();
+    // 空闲字节数等于低水位时不通知
+    assert_eq!(heap.pressure(), Pressure::Normal);
+    let addr3 = heap.alloc_(layout(16)).unwrap();
+    assert_eq!(PRESSURE_CALLS.take(), [(Pressure::Low, 1008)]);
+    // 在低水位以下时不重复通知
+    let addr4 = heap.alloc_(layout(16)).unwrap();
+    heap.dealloc_(addr4, layout(16));
+    assert!(PRESSURE_CALLS.take().is_empty());
+    // 回到低水位以上但仍低于高水位时不通知
+    heap.dealloc_(addr2, layout(1024));
+    assert_eq!(heap.pressure(), Pressure::Low);
+    heap.dealloc_(addr3, layout(16));
+    assert_eq!(PRESSURE_CALLS.take(), [(Pressure::Normal, 2048)]);
+    assert_eq!(heap.pressure(), Pressure::Normal);
+
+    // 修改水位后立即检查
+    heap.set_watermarks(4096, 4096);
+    assert_eq!(PRESSURE_CALLS.take(), [(Pressure::Low, 2048)]);
+    heap.set_watermarks(0, 0);
+    assert_eq!(PRESSURE_CALLS.take(), [(Pressure::Normal, 2048)]);
+    heap.dealloc_(addr1, layout(2048));
+    assert!(PRESSURE_CALLS.take().is_empty());
+}
+
+#[cfg(not(any(feature = "alloc-bitmap", feature = "red-zones")))]
+#[test]
+fn test_heap_quota() {
+    use crate::{QuotaError, QuotaHeap};
+    use core::ptr::NonNull;
+
+    #[repr(align(4096))]
+    struct Space([u8; 4096]);
+    let space = Space([0; 4096]);
+    let start = space.0.as_ptr() as usize;
+    let _base = lock_heap_base(start);
+    let heap = LockFreeHeap::<13>::new();
+    unsafe { heap.add_to_heap(start, start + 4096) };
+    let quota = QuotaHeap::<13, 2>::new(&heap);
+    quota.set_limit(0, 1024);
+    let layout = |size| Layout::from_size_align(size, 1).unwrap();
+
+    // 按块的实际大小计费
+    let addr1 = quota.alloc(0, layout(1000)).unwrap();
+    assert_eq!(quota.usage(0), 1024);
+    assert_eq!(quota.alloc(0, layout(1)), Err(QuotaError::QuotaExceeded));
+    let addr2 = quota.alloc(1, layout(2048)).unwrap();
+    assert_eq!(quota.usage(1), 2048);
+    // 堆内存不足时撤销预留
+    assert_eq!(quota.alloc(1, layout(2048)), Err(QuotaError::OutOfMemory));
+    assert_eq!(quota.usage(1), 2048);
+    assert_eq!(heap.stats_alloc_actual(), 3072);
+
+    // 上限低于当前用量
+    quota.set_limit(1, 1024);
+    assert_eq!(quota.alloc(1, layout(16)), Err(QuotaError::QuotaExceeded));
+    quota.dealloc(1, addr2, layout(2048));
+    quota.dealloc(0, addr1, layout(1000));
+    assert_eq!((quota.usage(0), quota.usage(1)), (0, 0));
+    assert_eq!(heap.stats_alloc_actual(), 0);
+
+    // 并发分配时用量不超过上限
+    quota.set_limit(0, 512);
+    let addrs = std::thread::scope(|s| {
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                s.spawn(|| {
+                    let mut addrs = Vec::new();
+                    while let Ok(addr) = quota.alloc(0, layout(16)) {
+                        addrs.push(addr.as_ptr() as usize);
+                    }
+                    addrs
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .flat_map(|handle| handle.join().unwrap())
+            .collect::<Vec<_>>()
+    });
+    assert_eq!(addrs.len(), 32);
+    assert_eq!(quota.usage(0), 512);
+    for addr in addrs {
+        quota.dealloc(0, NonNull::new(addr as *mut u8).unwrap(), layout(16));
+    }
+    assert_eq!(quota.usage(0), 0);
+    assert_eq!(heap.stats_alloc_actual(), 0);
+}
+
+#[cfg(feature = "alloc-bitmap")]
+#[test]
+fn test_heap_quota_invalid_free() {
+    use crate::{HeapError, QuotaHeap};
+
+    let heap = LockFreeHeap::<32>::new();
+    let space: [usize; 100] = [0; 100];
+    let _base = lock_heap_base(space.as_ptr() as usize);
+    unsafe {
+        heap.add_to_heap(space.as_ptr() as usize, space.as_ptr().add(100) as usize);
+    }
+    let quota = QuotaHeap::<32, 2>::new(&heap);
+    let layout = Layout::from_size_align(16, 1).unwrap();
+    let addr1 = quota.alloc(0, layout).unwrap();
+    let addr2 = quota.alloc(0, layout).unwrap();
+    let used = quota.usage(0);
+
+    // 重复释放被堆放弃，不改变用量
+    assert!(quota.dealloc(0, addr1, layout));
+    assert_eq!(quota.usage(0), used / 2);
+    assert!(!quota.dealloc(0, addr1, layout));
+    assert!(matches!(
+        LAST_HEAP_ERROR.take(),
+        Some(HeapError::DoubleFree { .. })
+    ));
+    assert_eq!(quota.usage(0), used / 2);
+
+    // 用错租户释放时用量不会回绕
+    assert!(quota.dealloc(1, addr2, layout));
+    assert_eq!(quota.usage(1), 0);
+    assert_eq!(heap.stats_alloc_actual(), 0);
+}
+
+#[cfg(not(any(feature = "alloc-bitmap", feature = "red-zones")))]
+#[test]
+fn test_heap_reserve() {
+    #[repr(align(4096))]
+    struct Space([u8; 4096]);
+    let space = Space([0; 4096]);
+    let start = space.0.as_ptr() as usize;
+    let _base = lock_heap_base(start);
+    let heap = LockFreeHeap::<13>::new();
+    unsafe { heap.add_to_heap(start, start + 4096) };
+    let layout = Layout::from_size_align(1000, 8).unwrap();
+
+    // 无法全部预留时归还已经预留的块
+    assert!(heap
+        .reserve(Layout::from_size_align(2048, 8).unwrap(), 3)
+        .is_none());
+    assert_eq!(heap.stats_alloc_actual(), 0);
+
+    let reservation = heap.reserve(layout, 4).unwrap();
+    assert_eq!(reservation.remaining(), 4);
+    assert_eq!(heap.stats_alloc_actual(), 4096);
+    // 堆已经用完，从预留中分配仍然成功
+    assert!(heap
+        .alloc_(Layout::from_size_align(16, 1).unwrap())
+        .is_err());
+    let addrs: Vec<_> = (0..4).map(|_| reservation.alloc().unwrap()).collect();
+    assert!(reservation.alloc().is_none());
+    for addr in &addrs {
+        assert_eq!(addr.as_ptr() as usize % 1024, 0);
+    }
+    reservation.dealloc(addrs[0]);
+    reservation.dealloc(addrs[1]);
+    assert_eq!(reservation.remaining(), 2);
+    assert_eq!(reservation.alloc().unwrap(), addrs[1]);
+
+    // drop 时归还剩余的块，取出的块由使用者释放
+    drop(reservation);
+    assert_eq!(heap.stats_alloc_actual(), 3072);
+    for addr in &addrs[1..] {
+        heap.dealloc_(*addr, layout);
+    }
+    assert_eq!(heap.stats_alloc_actual(), 0);
+
+    // 并发取出和放回时，剩余块数不会因为先 pop 后计数而小于 0
+    let small = Layout::from_size_align(16, 1).unwrap();
+    let reservation = heap.reserve(small, 4).unwrap();
+    std::thread::scope(|s| {
+        for _ in 0..4 {
+            s.spawn(|| {
+                for _ in 0..10000 {
+                    let block = reservation.alloc().unwrap();
+                    assert!(reservation.remaining() <= 4);
+                    reservation.dealloc(block);
+                    assert!(reservation.remaining() <= 4);
+                }
+            });
+        }
+    });
+    assert_eq!(reservation.remaining(), 4);
+    drop(reservation);
+
+    assert_eq!(heap.stats_alloc_actual(), 0);
+    assert!(heap
+        .alloc_(Layout::from_size_align(4096, 1).unwrap())
+        .is_ok());
+}
+
+#[cfg(feature = "poison")]
+#[test]
+fn test_heap_reserve_poison() {
+    use crate::HeapError;
+
+    let heap = LockFreeHeap::<32>::new();
+    let space: [usize; 100] = [0; 100];
+    let _base = lock_heap_base(space.as_ptr() as usize);
+    unsafe {
+        heap.add_to_heap(space.as_ptr() as usize, space.as_ptr().add(100) as usize);
+    }
+    let layout = Layout::from_size_align(8, 8).unwrap();
+
+    // 块在预留中存放时覆盖了红区，取出时重新设置
+    let reservation = heap.reserve(layout, 2).unwrap();
+    let block = reservation.alloc().unwrap();
+    heap.dealloc_(block, layout);
+    assert_eq!(LAST_HEAP_ERROR.take(), None);
+
+    drop(reservation);
+    assert_eq!(LAST_HEAP_ERROR.take(), None);
+
+    // 放回预留时检查红区
+    let layout = Layout::from_size_align(24, 8).unwrap();
+    let reservation = heap.reserve(layout, 1).unwrap();
+    let block = reservation.alloc().unwrap();
+    unsafe { block.as_ptr().add(24).write(0) };
+    reservation.dealloc(block);
+    assert_eq!(
+        LAST_HEAP_ERROR.take(),
+        Some(HeapError::RedZoneOverflow {
+            addr: block.as_ptr() as usize,
+            offset: 24
+        })
+    );
+
+    // 在预留中被写入，取出时报告
+    unsafe { block.as_ptr().add(20).write(0) };
+    assert_eq!(reservation.alloc(), Some(block));
+    assert_eq!(
+        LAST_HEAP_ERROR.take(),
+        Some(HeapError::UseAfterFree {
+            addr: block.as_ptr() as usize,
+            offset: 20
+        })
+    );
+    reservation.dealloc(block);
+    drop(reservation);
+    assert_eq!(LAST_HEAP_ERROR.take(), None);
+    assert_eq!(heap.stats_alloc_actual(), 0);
+}
+
+#[cfg(not(any(feature = "alloc-bitmap", feature = "red-zones")))]
+#[test]
+fn test_heap_sharded() {
+    use crate::ShardedHeap;
+    use core::ptr::NonNull;
+
+    #[repr(align(16384))]
+    struct Space([u8; 16384]);
+    let space = Space([0; 16384]);
+    let start = space.0.as_ptr() as usize;
+    let _base = lock_heap_base(start);
+    let heap = ShardedHeap::<13, 4>::new();
+    unsafe { heap.init(start, 16384) };
+    assert_eq!(heap.stats_total_bytes(), 16384);
+    let layout = |size| Layout::from_size_align(size, 1).unwrap();
+
+    // 按提示选择分片，内存不足时从之后的分片窃取
+    let addr1 = heap.alloc_on(5, layout(4096)).unwrap();
+    assert_eq!(addr1.as_ptr() as usize, start + 4096);
+    let addr2 = heap.alloc_on(1, layout(16)).unwrap();
+    assert_eq!(addr2.as_ptr() as usize, start + 8192);
+    assert_eq!(heap.shard(2).stats_alloc_actual(), 16);
+    for _ in 0..2 {
+        assert!(heap.alloc_on(0, layout(4096)).is_some());
+    }
+    assert!(heap.alloc_on(3, layout(4096)).is_none());
+
+    // 释放时归还给分配它的分片
+    heap.dealloc_(addr2, layout(16));
+    assert_eq!(heap.shard(2).stats_alloc_actual(), 0);
+    heap.dealloc_(addr1, layout(4096));
+    assert_eq!(heap.shard(1).stats_alloc_actual(), 0);
+    for i in [0, 3] {
+        let addr = (start + i * 4096) as *mut u8;
+        heap.dealloc_(NonNull::new(addr).unwrap(), layout(4096));
+    }
+    assert_eq!(heap.stats_alloc_actual(), 0);
+
+    // 在其它线程上释放
+    std::thread::scope(|s| {
+        for hint in 0..4 {
+            let heap = &heap;
+            s.spawn(move || {
+                for _ in 0..1000 {
+                    let addrs: Vec<_> = (0..8)
+                        .map(|_| heap.alloc_on(hint, layout(256)).unwrap().as_ptr() as usize)
+                        .collect();
+                    for addr in addrs {
+                        let other = (addr - start) / 4096;
+                        assert!(heap.shard(other).stats_alloc_actual() > 0);
+                        heap.dealloc_(NonNull::new(addr as *mut u8).unwrap(), layout(256));
+                    }
+                }
+            });
+        }
+    });
+    assert_eq!(heap.stats_alloc_actual(), 0);
+    assert_eq!(heap.stats_total_bytes(), 16384);
+}
+
+#[cfg(feature = "heap-grow")]
+#[test]
+fn test_heap_sharded_grow() {
+    use crate::region::MAX_REGIONS;
+    use crate::ShardedHeap;
+    use core::ptr::NonNull;
+
+    const CHUNK: usize = 256;
+    const SHARDS: usize = 2;
+    const NUM_CHUNKS: usize = SHARDS * MAX_REGIONS + 4;
+
+    let backing = Layout::from_size_align(CHUNK * NUM_CHUNKS, CHUNK).unwrap();
+    let base = unsafe { std::alloc::alloc(backing) } as usize;
+    let _base = lock_heap_base(base);
+    let layout = Layout::from_size_align(CHUNK / 4, 1).unwrap();
+
+    // 每个分片都扩展到区域表满
+    let heap = ShardedHeap::<16, SHARDS>::new();
+    GROW_REGIONS.with_borrow_mut(|regions| {
+        regions.extend((0..NUM_CHUNKS).map(|i| (base + i * CHUNK, base + (i + 1) * CHUNK)))
+    });
+    let mut addrs = Vec::new();
+    for hint in 0.. {
+        match heap.alloc_on(hint, layout) {
+            Some(addr) => addrs.push(addr.as_ptr() as usize),
+            None => break,
+        }
+    }
+    assert_eq!(
+        GROW_REGIONS.with_borrow(|regions| regions.len()),
+        NUM_CHUNKS - SHARDS * MAX_REGIONS
+    );
+    assert!((0..SHARDS).all(|i| heap.shard(i).stats_alloc_actual() > 0));
+
+    // 在其它线程上交替释放各个分片的块
+    std::thread::scope(|s| {
+        let heap = &heap;
+        for part in 0..2 {
+            let addrs: Vec<_> = addrs.iter().copied().skip(part).step_by(2).collect();
+            s.spawn(move || {
+                for addr in addrs.into_iter().rev() {
+                    heap.dealloc_(NonNull::new(addr as *mut u8).unwrap(), layout);
+                }
+            });
+        }
+    });
+    assert_eq!(heap.stats_alloc_actual(), 0);
+
+    GROW_REGIONS.with_borrow_mut(|regions| regions.clear());
+    unsafe { std::alloc::dealloc(base as *mut u8, backing) };
+}
+
+#[cfg(not(feature = "alloc-bitmap"))]
+#[test]
+fn test_static_heap() {
+    let _base = lock_heap_base(0);
+    crate::static_heap! {
+        static HEAP: StaticHeap<13> = 16384;
+    }
+
+    // 多个线程同时第一次使用时只初始化一次
+    std::thread::scope(|s| {
+        for _ in 0..8 {
+            s.spawn(|| unsafe {
+                let layout = Layout::from_size_align(64, 8).unwrap();
+                let addr = HEAP.alloc(layout);
+                assert!(!addr.is_null());
+                HEAP.dealloc(addr, layout);
+            });
+        }
+    });
+    assert_eq!(HEAP.stats_total_bytes(), 16384);
+    assert_eq!(HEAP.stats_alloc_actual(), 0);
+    // 释放的块全部合并为最大的块，启用红区时块的大小也不变
+    for _ in 0..4 {
+        assert!(HEAP
+            .alloc_(Layout::from_size_align(2049, 1).unwrap())
+            .is_ok());
+    }
+}
+
+#[cfg(feature = "percpu-stats")]
+std::thread_local! {
+    /// 当前线程所模拟的 CPU 编号
+    static CURRENT_CPU: core::cell::Cell<usize> = const { core::cell::Cell::new(0) };
+}
+
+#[cfg(feature = "percpu-stats")]
+struct CpuHintImpl;
+
+#[cfg(feature = "percpu-stats")]
+#[crate_interface::impl_interface]
+impl crate::CpuHint for CpuHintImpl {
+    fn current_cpu() -> usize {
+        CURRENT_CPU.get()
+    }
+}
+
+/// 块在一个 CPU 上分配、在另一个 CPU 上释放时，统计信息仍然正确
+#[cfg(feature = "percpu-stats")]
+#[test]
+fn test_percpu_stats() {
+    let space: [usize; 256] = [0; 256];
+    let start = space.as_ptr() as usize;
+    let _base = lock_heap_base(start);
+    let heap = LockFreeHeap::<32>::new();
+    unsafe { heap.add_to_heap(start, start + 256 * size_of::<usize>()) };
+    let layout = Layout::from_size_align(24, 8).unwrap();
+
+    CURRENT_CPU.set(1);
+    let ptr = heap.alloc_(layout).unwrap();
+    assert_eq!(heap.stats_alloc_user(), 24);
+    CURRENT_CPU.set(6);
+    heap.dealloc_(ptr, layout);
+    assert_eq!(heap.stats_alloc_user(), 0);
+    assert_eq!(heap.stats_alloc_actual(), 0);
+    CURRENT_CPU.set(0);
+}
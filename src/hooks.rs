@@ -0,0 +1,45 @@
+//! 堆事件的回调接口
+//!
+//! 启用 `alloc-hooks` 后，需要像 `GetDataBase` 一样，
+//! 使用 `crate_interface::impl_interface` 实现 [`AllocHook`]。
+//! 回调在分配或释放的过程中同步调用，不应在其中使用同一个堆分配内存。
+
+use core::alloc::Layout;
+
+/// 堆事件
+/// 其中的地址均为实际地址，order 为块大小的以 2 为底的对数，
+/// tag 为调用 `alloc_with_tag`/`dealloc_with_tag` 时传入的值，其它接口传入 0
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum HeapEvent {
+    /// 分配成功
+    Alloc {
+        addr: usize,
+        layout: Layout,
+        order: usize,
+        tag: usize,
+    },
+    /// 释放
+    Free {
+        addr: usize,
+        layout: Layout,
+        order: usize,
+        tag: usize,
+    },
+    /// addr 处大小为 2^order 的块被切分为两个大小为 2^(order-1) 的块
+    Split { addr: usize, order: usize },
+    /// 两个大小为 2^(order-1) 的伙伴块被合并为 addr 处大小为 2^order 的块
+    Merge { addr: usize, order: usize },
+    /// 没有满足 layout 的空闲块
+    OutOfMemory { layout: Layout, tag: usize },
+}
+
+/// 堆事件的回调函数，由使用者实现
+#[crate_interface::def_interface]
+pub trait AllocHook {
+    fn on_heap_event(event: HeapEvent);
+}
+
+pub(crate) fn emit(event: HeapEvent) {
+    crate_interface::call_interface!(AllocHook::on_heap_event, event)
+}
@@ -1,12 +1,43 @@
-use super::linked_list::LinkedList;
+use super::cache_padded::HeadPadded;
+use super::dump::{BinaryWriter, DumpError, DumpRegion, DumpSink, DumpStats, TextWriter};
+use super::linked_list::{LinkedList, NODE_SIZE};
+use super::region::RegionTable;
+use super::reserve::Reservation;
+use super::stats::ShardedCounter;
+#[cfg(feature = "heap-grow")]
+use super::sync::spin_loop;
+use super::sync::{AtomicUsize, Ordering};
+
+#[cfg(feature = "alloc-bitmap")]
+use super::alloc_bitmap;
+#[cfg(any(feature = "alloc-bitmap", feature = "poison"))]
+use super::error;
+#[cfg(feature = "heap-grow")]
+use super::grow;
+#[cfg(feature = "alloc-hooks")]
+use super::hooks::{self, HeapEvent};
+#[cfg(feature = "leak-registry")]
+use super::leak_registry::{LeakRegistry, Leaks};
+#[cfg(feature = "poison")]
+use super::poison;
+#[cfg(feature = "heap-release")]
+use super::release;
+#[cfg(feature = "watermarks")]
+use super::watermark::{Pressure, Watermarks};
+
+/// 触发堆事件，未启用 `alloc-hooks` 时展开为空，参数不会被求值
+macro_rules! emit_event {
+    ($event:expr) => {
+        #[cfg(feature = "alloc-hooks")]
+        hooks::emit($event);
+    };
+}
 
 use core::alloc::GlobalAlloc;
 use core::alloc::Layout;
 use core::cmp::{max, min};
 use core::fmt;
-use core::mem::size_of;
 use core::ptr::NonNull;
-use core::sync::atomic::{AtomicUsize, Ordering};
 
 /// A heap that uses buddy system with configurable order.
 ///
@@ -31,41 +62,115 @@ pub struct LockFreeHeap<const ORDER: usize> {
     // buddy system with max order of `ORDER`
     // LinkedList已经实现了无锁同步，因此本文件中涉及LinkedList的单个操作同步问题可以不需理会。
     // 但是，多个操作间的数据一致性仍需考虑。
-    free_list: [LinkedList; ORDER],
+    // 启用 `padded-heads` 时每个链表头独占一个缓存行
+    free_list: [HeadPadded<LinkedList>; ORDER],
+
+    // 加入堆的内存区域
+    regions: RegionTable,
+
+    // 通过 release_free 释放了物理内存的块，released[i] 中的块大小为 2^i
+    #[cfg(feature = "heap-release")]
+    released: [LinkedList; ORDER],
+
+    // 是否有线程正在扩展堆，为 0 或 1
+    #[cfg(feature = "heap-grow")]
+    growing: AtomicUsize,
+
+    // 尚未释放的块
+    #[cfg(feature = "leak-registry")]
+    leak_registry: LeakRegistry,
 
     // statistics
-    user: AtomicUsize,
-    allocated: AtomicUsize,
+    // user 与 allocated 在每次分配和释放时都会修改，因此分片存储；total 只在加入区域时修改
+    user: ShardedCounter,
+    allocated: ShardedCounter,
     total: AtomicUsize,
+
+    #[cfg(feature = "watermarks")]
+    watermarks: Watermarks,
 }
 
 impl<const ORDER: usize> LockFreeHeap<ORDER> {
-    /// Create an empty heap
-    pub const fn new() -> Self {
-        Self {
-            free_list: [LinkedList::EMPTY_LIST; ORDER],
-            user: AtomicUsize::new(0),
-            allocated: AtomicUsize::new(0),
-            total: AtomicUsize::new(0),
+    const_fn! {
+        /// Create an empty heap
+        pub fn new() -> Self {
+            Self {
+                #[cfg(not(any(loom, shuttle)))]
+                free_list: [const { HeadPadded::new(LinkedList::EMPTY_LIST) }; ORDER],
+                #[cfg(any(loom, shuttle))]
+                free_list: core::array::from_fn(|_| HeadPadded::new(LinkedList::new())),
+                regions: RegionTable::new(),
+                #[cfg(all(feature = "heap-release", not(any(loom, shuttle))))]
+                released: [const { LinkedList::EMPTY_LIST }; ORDER],
+                #[cfg(all(feature = "heap-release", any(loom, shuttle)))]
+                released: core::array::from_fn(|_| LinkedList::new()),
+                #[cfg(feature = "heap-grow")]
+                growing: AtomicUsize::new(0),
+                #[cfg(feature = "leak-registry")]
+                leak_registry: LeakRegistry::new(),
+                user: ShardedCounter::new(),
+                allocated: ShardedCounter::new(),
+                total: AtomicUsize::new(0),
+                #[cfg(feature = "watermarks")]
+                watermarks: Watermarks::new(),
+            }
         }
     }
 
-    /// Create an empty heap
-    pub const fn empty() -> Self {
-        Self::new()
+    const_fn! {
+        /// Create an empty heap
+        pub fn empty() -> Self {
+            Self::new()
+        }
     }
 
     /// Add a range of memory [start, end) to the heap
-    pub unsafe fn add_to_heap(&self, mut start: usize, mut end: usize) {
+    /// 堆最多记录 `MAX_REGIONS` 个区域，超出时 panic
+    pub unsafe fn add_to_heap(&self, start: usize, end: usize) {
+        if !self.add_region(start, end) {
+            // 转储、检查与 `owns` 都通过区域表查找区域，因此区域表满时不能再加入新区域
+            panic!("too many heap regions");
+        }
+    }
+
+    /// 将 [start, end) 加入堆并记录在区域表中。区域表已满时不加入并返回 false
+    pub(crate) unsafe fn add_region(&self, mut start: usize, mut end: usize) -> bool {
         // avoid unaligned access on some platforms
-        start = (start + size_of::<[usize; 2]>() - 1) & (!size_of::<[usize; 2]>() + 1);
-        end &= !size_of::<[usize; 2]>() + 1;
+        start = (start + MIN_BLOCK_SIZE - 1) & (!MIN_BLOCK_SIZE + 1);
+        end &= !MIN_BLOCK_SIZE + 1;
         assert!(start <= end);
 
+        // 启用分配位图时，区域开头的一部分用于存放位图
+        let region_start = start;
+        #[cfg(feature = "alloc-bitmap")]
+        {
+            start = alloc_bitmap::reserve(start, end);
+        }
+        if !self.regions.register(region_start, start, end) {
+            return false;
+        }
+
+        #[cfg(feature = "debug-checks")]
+        for list in self.free_list.iter() {
+            list.add_node_bounds(start, end);
+        }
+        #[cfg(all(feature = "debug-checks", feature = "heap-release"))]
+        for list in self.released.iter() {
+            list.add_node_bounds(start, end);
+        }
+        #[cfg(feature = "node-canary")]
+        for (order, list) in self.free_list.iter().enumerate() {
+            list.set_order(order);
+        }
+        #[cfg(all(feature = "node-canary", feature = "heap-release"))]
+        for (order, list) in self.released.iter().enumerate() {
+            list.set_order(order);
+        }
+
         let mut total = 0;
         let mut current_start = start;
 
-        while current_start + size_of::<[usize; 2]>() <= end {
+        while current_start + MIN_BLOCK_SIZE <= end {
             let lowbit = current_start & (!current_start + 1);
             let mut size = min(lowbit, prev_power_of_two(end - current_start));
 
@@ -78,11 +183,16 @@ impl<const ORDER: usize> LockFreeHeap<ORDER> {
             }
             total += size;
 
+            #[cfg(feature = "poison")]
+            poison::poison_free(current_start, size);
             self.free_list[order].push(current_start as *mut _); // 写
             current_start += size;
         }
 
-        self.total.fetch_add(total, Ordering::SeqCst); // 写
+        self.total.fetch_add(total, Ordering::Relaxed); // 写
+        #[cfg(feature = "watermarks")]
+        self.update_pressure();
+        true
     }
 
     /// Add a range of memory [start, start+size) to the heap
@@ -93,102 +203,425 @@ impl<const ORDER: usize> LockFreeHeap<ORDER> {
     /// Alloc a range of memory from the heap satifying `layout` requirements
     /// 返回值是偏移量
     pub fn alloc_(&self, layout: Layout) -> Result<NonNull<u8>, ()> {
-        let size = max(
-            layout.size().next_power_of_two(),
-            max(layout.align(), size_of::<[usize; 2]>()),
-        );
+        self.alloc_with_tag(layout, 0).ok_or(())
+    }
+
+    /// 与 `alloc_` 相同，内存不足时返回 None。tag 会随堆事件传给 `AllocHook`，并记录在 `leaks` 返回的块中
+    #[cfg_attr(
+        not(any(feature = "alloc-hooks", feature = "leak-registry")),
+        allow(unused_variables)
+    )]
+    pub fn alloc_with_tag(&self, layout: Layout, tag: usize) -> Option<NonNull<u8>> {
+        let result = self.alloc_block(layout, tag);
+        #[cfg(feature = "heap-grow")]
+        let result = result.or_else(|| self.grow_and_alloc(layout, tag));
+        if result.is_none() {
+            emit_event!(HeapEvent::OutOfMemory { layout, tag });
+        }
+        result
+    }
+
+    /// 分配一个满足 layout 的块，没有空闲块时返回 None
+    #[cfg_attr(
+        not(any(feature = "alloc-hooks", feature = "leak-registry")),
+        allow(unused_variables)
+    )]
+    fn alloc_block(&self, layout: Layout, tag: usize) -> Option<NonNull<u8>> {
+        let size = block_size(layout);
         let class = size.trailing_zeros() as usize;
-        let mut current_block;
-        for i in class..self.free_list.len() {
+        // 每个块都按其大小对齐，因此对齐要求大于块大小时，从至少与对齐要求一样大的块中切分出开头的部分；
+        // 没有这样的块时（包括对齐要求大于最大块的情况），查找起始地址恰好满足对齐要求的较小的块
+        let align_class = layout.align().trailing_zeros() as usize;
+        let taken = self
+            .pop_block(max(class, align_class))
+            .or_else(|| self.take_aligned_block(class, align_class));
+        #[cfg(feature = "heap-release")]
+        let taken = taken.or_else(|| self.reacquire_block(max(class, align_class)));
+        let (block, i) = taken?;
+        // 判断块是否需要切分，若 i == class，则不需要进行切分
+        for j in (class + 1..i + 1).rev() {
+            emit_event!(HeapEvent::Split {
+                addr: block,
+                order: j
+            });
+            fault_point!(crate::fault::FaultPoint::AllocSplit {
+                addr: block,
+                order: j
+            });
+            // 将分裂后的块插入 free_list[j-1]
+            unsafe { self.free_list[j - 1].push((block + (1 << (j - 1))) as *mut _) };
+        }
+        // 执行到这里时，说明已经分配成功了
+        let result = NonNull::new(block as *mut u8).unwrap();
+        #[cfg(feature = "alloc-bitmap")]
+        alloc_bitmap::mark_alloc(&self.regions, block, class);
+        #[cfg(feature = "poison")]
+        if let Err(err) = unsafe { poison::check_alloc(block, size, layout.size()) } {
+            error::report(err);
+        }
+        #[cfg(feature = "leak-registry")]
+        self.leak_registry.insert(block, layout, tag);
+        self.user.add(block, layout.size()); // 写user
+        self.allocated.add(block, size); // 写allocater
+        #[cfg(feature = "watermarks")]
+        self.update_pressure();
+        emit_event!(HeapEvent::Alloc {
+            addr: block,
+            layout,
+            order: class,
+            tag
+        });
+        Some(result)
+    }
+
+    /// 没有空闲块时调用 `HeapGrow` 扩展堆，然后重试分配，直到成功或无法扩展。
+    /// 同一时刻只有一个线程在扩展，其它线程等待其完成后直接重试
+    #[cfg(feature = "heap-grow")]
+    fn grow_and_alloc(&self, layout: Layout, tag: usize) -> Option<NonNull<u8>> {
+        loop {
+            if self
+                .growing
+                .compare_exchange(0, 1, Ordering::Acquire, Ordering::Relaxed)
+                .is_ok()
+            {
+                // 区域表已满时不再扩展，加入区域表中没有记录的区域会使转储和检查看不到这部分内存
+                let added = !self.regions.is_full()
+                    && grow::grow(layout)
+                        .is_some_and(|(start, end)| unsafe { self.add_region(start, end) });
+                self.growing.store(0, Ordering::Release);
+                if !added {
+                    return None;
+                }
+            } else {
+                while self.growing.load(Ordering::Acquire) != 0 {
+                    spin_loop();
+                }
+            }
+            if let Some(ptr) = self.alloc_block(layout, tag) {
+                return Some(ptr);
+            }
+        }
+    }
+
+    /// 取回一个 release_free 释放的、大小至少为 2^order 的块，返回块的地址及其所在的级别
+    #[cfg(feature = "heap-release")]
+    fn reacquire_block(&self, order: usize) -> Option<(usize, usize)> {
+        (order..ORDER).find_map(|i| {
+            let block = self.released[i].pop()? as usize;
+            release::reacquire(block, 1 << i);
+            // 物理内存被释放后内容可能已经改变，重新毒化以免被误报为释放后写入
+            #[cfg(feature = "poison")]
+            unsafe {
+                poison::poison_free(block, 1 << i)
+            };
+            self.total.fetch_add(1 << i, Ordering::Relaxed);
+            Some((block, i))
+        })
+    }
+
+    /// 从 free_list[order] 及更大的空闲链表中取出一个块，返回块的地址及其所在的级别
+    fn pop_block(&self, order: usize) -> Option<(usize, usize)> {
+        for i in order..self.free_list.len() {
             if !self.free_list[i].is_empty() {
                 // 先尝试从列表中取出一个块，如果当前的这个链表在判断非空之后无法取出块，则跳过后续的流程，尝试从下一个链表中取出空闲块
-                current_block = self.free_list[i].pop();
-                if current_block.is_none() {
+                match self.free_list[i].pop() {
+                    Some(block) => return Some((block as usize, i)),
                     // 这里直接使用 continue 会导致在分配时出现大空闲块未切分完成，取不到空闲块的情况
                     // 这里需要限制 ORDER 的大小，ORDER 的大小决定了分配和合并时占用的时间
                     // ORDER 小一点，可以快速结束切分以及合并的过程，从而出现这个问题的概率
-                    continue;
+                    None => continue,
                 }
-                assert!(current_block.is_some());
-                // 判断块是否需要切分，若 i == class，则不需要进行切分
-                if i != class {
-                    for j in (class + 1..i + 1).rev() {
-                        let block = current_block.unwrap() as usize;
-                        // 将分裂后的块插入 free_list[j-1]
-                        unsafe { self.free_list[j - 1].push((block + (1 << (j - 1))) as *mut _) };
-                        current_block = Some(block as _);
-                    }
+            }
+        }
+        None
+    }
+
+    /// 在 free_list[class..align_class] 中从小到大查找起始地址按 2^align_class 对齐的块并将其取出，
+    /// 返回块的地址及其所在的级别。需要遍历链表，因此只在 `pop_block` 失败时使用
+    fn take_aligned_block(&self, class: usize, align_class: usize) -> Option<(usize, usize)> {
+        let align = 1usize.checked_shl(align_class as u32)?;
+        for i in class..min(align_class, self.free_list.len()) {
+            let mut found = None;
+            self.free_list[i].for_each_node(|node| {
+                if found.is_none() && (node as usize).is_multiple_of(align) {
+                    found = Some(node);
                 }
-                // 执行到这里时，说明已经分配成功了
-                let result = NonNull::new(current_block.unwrap() as *mut u8).unwrap();
-                self.user.fetch_add(layout.size(), Ordering::SeqCst); // 写user
-                self.allocated.fetch_add(size, Ordering::SeqCst); // 写allocater
-                return Ok(result);
+            });
+            // 块可能已经被其它线程取走，此时继续查找更大的块
+            if let Some(node) = found.filter(|&node| self.free_list[i].delete(node)) {
+                return Some((node as usize, i));
             }
         }
-        Err(())
+        None
     }
 
     /// Dealloc a range of memory from the heap
     /// ptr 参数为偏移量
     /// 这个函数的写操作太多了，不好同步。看看能否减少，比如先插入再合并改为先合并再插入。
     pub fn dealloc_(&self, ptr: NonNull<u8>, layout: Layout) {
-        let size = max(
-            layout.size().next_power_of_two(),
-            max(layout.align(), size_of::<[usize; 2]>()),
-        );
+        self.dealloc_with_tag(ptr, layout, 0);
+    }
+
+    /// 与 `dealloc_` 相同，tag 会随堆事件传给 `AllocHook`。
+    /// 返回块是否被释放，启用 `alloc-bitmap` 时检查失败的释放会被放弃并返回 false
+    #[cfg_attr(not(feature = "alloc-hooks"), allow(unused_variables))]
+    pub fn dealloc_with_tag(&self, ptr: NonNull<u8>, layout: Layout, tag: usize) -> bool {
+        let size = block_size(layout);
         let class = size.trailing_zeros() as usize;
 
+        // 检查失败时，报告错误并放弃本次释放，避免破坏空闲链表
+        #[cfg(feature = "alloc-bitmap")]
+        if let Err(err) = alloc_bitmap::mark_free(&self.regions, ptr.as_ptr() as usize, class) {
+            error::report(err);
+            return false;
+        }
+        #[cfg(feature = "poison")]
+        if let Err(err) = unsafe { poison::check_free(ptr.as_ptr() as usize, size, layout.size()) }
+        {
+            error::report(err);
+        }
+
+        emit_event!(HeapEvent::Free {
+            addr: ptr.as_ptr() as usize,
+            layout,
+            order: class,
+            tag
+        });
+        // 块加入空闲链表后可能立即被其它线程分配，因此需要在此之前注销
+        #[cfg(feature = "leak-registry")]
+        self.leak_registry.remove(ptr.as_ptr() as usize);
+
         unsafe {
             // 合并空闲块
             let mut current_ptr = ptr.as_ptr() as usize;
             let mut current_class = class;
 
-            while current_class < self.free_list.len() - 1 {
+            loop {
                 let buddy = current_ptr ^ (1 << current_class);
                 // 返回 true，当前级别的空闲链表中存在可以合并的节点且已经被删除，可以直接合并
-                if self.free_list[current_class].delete(buddy as _) {
-                    current_ptr = min(current_ptr, buddy);
-                    current_class += 1;
+                let merged = if current_class < self.free_list.len() - 1
+                    && self.take_buddy(buddy, current_class)
+                {
+                    Some(min(current_ptr, buddy))
                 } else {
                     // 没有可以合并的块，插入到当前的空闲链表中
+                    #[cfg(feature = "poison")]
+                    poison::poison_free(current_ptr, 1 << current_class);
                     self.free_list[current_class].push(current_ptr as *mut _); // 写free_list[current_class]
+                    self.take_pushed_buddies(current_ptr, current_class)
+                };
+                let Some(merged) = merged else {
                     break;
-                }
+                };
+                current_ptr = merged;
+                current_class += 1;
+                emit_event!(HeapEvent::Merge {
+                    addr: current_ptr,
+                    order: current_class
+                });
+            }
+        }
+
+        let addr = ptr.as_ptr() as usize;
+        self.user.sub(addr, layout.size()); // 写user
+        self.allocated.sub(addr, size); // 写allocater
+        #[cfg(feature = "watermarks")]
+        self.update_pressure();
+        true
+    }
+
+    /// 将伙伴 buddy 从 free_list[class] 中取出用于合并。
+    /// 伙伴的物理内存可能已被 `release_free` 释放，此时从 released[class] 中取出并取回其物理内存
+    fn take_buddy(&self, buddy: usize, class: usize) -> bool {
+        if self.free_list[class].delete(buddy as _) {
+            return true;
+        }
+        #[cfg(feature = "heap-release")]
+        if self.released[class].delete(buddy as _) {
+            release::reacquire(buddy, 1 << class);
+            self.total.fetch_add(1 << class, Ordering::Relaxed);
+            return true;
+        }
+        false
+    }
+
+    /// block 已经插入 free_list[class]。伙伴可能在查找它之后、插入 block 之前被另一个线程释放并插入链表，
+    /// 两个线程都没有找到对方，因此插入后再检查一次伙伴是否在链表中。
+    /// 二者都在时按地址从低到高将其取出，取出较低地址块的线程负责合并，另一个线程直接返回。
+    /// 较高地址块已被取走时将较低地址块放回；若较高地址块随后又回到链表中，则重新尝试。
+    /// 两个块都被取出时返回合并后的块
+    fn take_pushed_buddies(&self, block: usize, class: usize) -> Option<usize> {
+        if class == self.free_list.len() - 1 {
+            return None;
+        }
+        let list = &self.free_list[class];
+        let buddy = block ^ (1 << class);
+        let (low, high) = (min(block, buddy), max(block, buddy));
+        // 伙伴不在链表中时，之后插入伙伴的线程能看到 block
+        if !list.contains(buddy as _) {
+            return None;
+        }
+        loop {
+            if !list.delete(low as _) {
+                return None;
+            }
+            if list.delete(high as _) {
+                return Some(low);
             }
+            unsafe { list.push(low as _) };
+            if !list.contains(high as _) {
+                return None;
+            }
+        }
+    }
 
-            // 此时合并的块无法在循环中 push 回链表，因此在此处push
-            if current_class == self.free_list.len() - 1 {
-                self.free_list[current_class].push(current_ptr as *mut _); // 写free_list[current_class]
+    /// 从堆中分配 n 个满足 layout 的块放入预留，之后可以从预留中无失败地分配，见 [`Reservation`]。
+    /// 无法全部分配时归还已经分配的块并返回 None，期间并发的分配可能因为这些块暂时被占用而失败
+    pub fn reserve(&self, layout: Layout, n: usize) -> Option<Reservation<'_, ORDER>> {
+        let reservation = Reservation::new(self, layout);
+        for _ in 0..n {
+            // 失败时 drop reservation，归还已经预留的块
+            reservation.dealloc(self.alloc_(layout).ok()?);
+        }
+        Some(reservation)
+    }
+
+    /// 将大小至少为 2^min_order 的空闲块移出空闲链表，通过 `HeapRelease` 释放其物理内存，
+    /// 返回释放的字节数。之后分配找不到空闲块时，会取回这些块
+    #[cfg(feature = "heap-release")]
+    pub fn release_free(&self, min_order: usize) -> usize {
+        let mut released = 0;
+        for order in min_order.max(MIN_BLOCK_SIZE.trailing_zeros() as usize)..ORDER {
+            while let Some(block) = self.free_list[order].pop() {
+                let block = block as usize;
+                self.total.fetch_sub(1 << order, Ordering::Relaxed);
+                release::release(block, 1 << order);
+                unsafe { self.released[order].push(block as *mut _) };
+                released += 1 << order;
             }
         }
+        #[cfg(feature = "watermarks")]
+        self.update_pressure();
+        released
+    }
+
+    /// 释放所有最大的空闲块的物理内存，见 [`Self::release_free`]
+    #[cfg(feature = "heap-release")]
+    pub fn trim(&self) -> usize {
+        self.release_free(ORDER - 1)
+    }
 
-        self.user.fetch_sub(layout.size(), Ordering::SeqCst); // 写user
-        self.allocated.fetch_sub(size, Ordering::SeqCst); // 写allocater
+    /// 设置空闲字节数的低水位与高水位，low 为 0 时不进行检查，见 [`MemoryPressure`](crate::MemoryPressure)
+    #[cfg(feature = "watermarks")]
+    pub fn set_watermarks(&self, low: usize, high: usize) {
+        self.watermarks.set(low, high);
+        self.update_pressure();
+    }
+
+    /// 当前的内存压力状态
+    #[cfg(feature = "watermarks")]
+    pub fn pressure(&self) -> Pressure {
+        self.watermarks.pressure()
+    }
+
+    #[cfg(feature = "watermarks")]
+    fn update_pressure(&self) {
+        self.watermarks.update(|| {
+            self.stats_total_bytes()
+                .saturating_sub(self.stats_alloc_actual())
+        });
+    }
+
+    /// 使用 [start, start + size) 记录尚未释放的块，每个块占用一个表项，表项大小为 `4 * size_of::<usize>()`
+    /// 表满时分配仍会成功，但块不会被记录，见 [`Self::leak_registry_overflow`]
+    ///
+    /// # Safety
+    ///
+    /// 该内存区域需要有效、按 usize 对齐，且不能与堆的区域重叠；
+    /// 应在堆开始分配之前调用，在此之前分配的块不会被记录
+    #[cfg(feature = "leak-registry")]
+    pub unsafe fn init_leak_registry(&self, start: usize, size: usize) {
+        self.leak_registry.init(start, size);
+    }
+
+    /// 遍历所有尚未释放的块
+    #[cfg(feature = "leak-registry")]
+    pub fn leaks(&self) -> Leaks<'_> {
+        self.leak_registry.iter()
+    }
+
+    /// 因为登记表已满而没有记录的块的数量
+    #[cfg(feature = "leak-registry")]
+    pub fn leak_registry_overflow(&self) -> usize {
+        self.leak_registry.overflow()
+    }
+
+    /// 将堆的区域、空闲块和统计信息以二进制格式写入 buf，返回写入的字节数，格式见 [`crate::HeapDump`]
+    /// buf 不足时返回 `DumpError::BufferTooSmall`，其中包含所需的字节数
+    pub fn dump(&self, buf: &mut [u8]) -> Result<usize, DumpError> {
+        let mut writer = BinaryWriter::new(buf);
+        self.dump_to(&mut writer);
+        writer.finish()
+    }
+
+    /// 以可读的文本格式输出与 `dump` 相同的内容
+    pub fn dump_text(&self, w: &mut impl fmt::Write) -> fmt::Result {
+        let mut writer = TextWriter::new(w);
+        self.dump_to(&mut writer);
+        writer.finish()
+    }
+
+    fn dump_to(&self, sink: &mut impl DumpSink) {
+        let base = crate::get_data_base();
+        sink.header(base, ORDER);
+        sink.stats(DumpStats {
+            user: self.stats_alloc_user(),
+            allocated: self.stats_alloc_actual(),
+            total: self.stats_total_bytes(),
+        });
+        sink.regions_begin();
+        for region in self.regions.iter() {
+            sink.region(DumpRegion {
+                start: region.start.wrapping_sub(base),
+                data: region.data.wrapping_sub(base),
+                end: region.end.wrapping_sub(base),
+            });
+        }
+        sink.regions_end();
+        for (order, list) in self.free_list.iter().enumerate() {
+            sink.free_list_begin(order);
+            list.for_each_node(|node| sink.free_block((node as usize).wrapping_sub(base)));
+            sink.free_list_end();
+        }
+    }
+
+    /// addr 是否位于某个已记录区域的可分配部分
+    pub(crate) fn owns(&self, addr: usize) -> bool {
+        self.regions.find(addr).is_some()
     }
 
     /// Return the number of bytes that user requests
     pub fn stats_alloc_user(&self) -> usize {
-        self.user.load(Ordering::SeqCst)
+        self.user.sum()
     }
 
     /// Return the number of bytes that are actually allocated
     pub fn stats_alloc_actual(&self) -> usize {
-        self.allocated.load(Ordering::SeqCst)
+        self.allocated.sum()
     }
 
     /// Return the total number of bytes in the heap
     pub fn stats_total_bytes(&self) -> usize {
-        self.total.load(Ordering::SeqCst)
+        self.total.load(Ordering::Relaxed)
     }
 }
 
 impl<const ORDER: usize> fmt::Debug for LockFreeHeap<ORDER> {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
         fmt.debug_struct("LockFreeHeap")
-            .field("user", &self.user)
-            .field("allocated", &self.allocated)
-            .field("total", &self.total)
+            .field("user", &self.stats_alloc_user())
+            .field("allocated", &self.stats_alloc_actual())
+            .field("total", &self.stats_total_bytes())
             .finish()
     }
 }
@@ -205,6 +638,19 @@ unsafe impl<const ORDER: usize> GlobalAlloc for LockFreeHeap<ORDER> {
     }
 }
 
+/// 最小块的大小，每个空闲块的开头需要能放下一个链表节点
+pub(crate) const MIN_BLOCK_SIZE: usize = NODE_SIZE.next_power_of_two();
+
+/// 满足 layout 的块大小，启用 `red-zones` 时包括红区。
+/// 对齐要求不影响块的大小，见 `LockFreeHeap::alloc_with_tag`
+pub(crate) fn block_size(layout: Layout) -> usize {
+    #[cfg(not(feature = "red-zones"))]
+    let size = layout.size();
+    #[cfg(feature = "red-zones")]
+    let size = layout.size() + poison::RED_ZONE_SIZE;
+    max(size.next_power_of_two(), MIN_BLOCK_SIZE)
+}
+
 pub(crate) fn prev_power_of_two(num: usize) -> usize {
     1 << (usize::BITS as usize - num.leading_zeros() as usize - 1)
 }
@@ -0,0 +1,193 @@
+//! 已分配块的登记表
+//!
+//! 登记表是位于使用者提供的内存中的开放寻址散列表，以块地址为键，记录每个尚未释放的块的 layout 和 tag。
+//! 与链表节点相同，表的地址和块地址都以相对于数据段基地址的偏移量存储，从而保证位置无关。
+//!
+//! 表项的键有以下几种取值：
+//! - [`EMPTY`]：空闲的表项，块被释放后表项立即变回空闲
+//! - [`BUSY`]：正在写入的表项
+//! - 其它：块地址的偏移量加上 [`KEY_FLAG`]。块地址至少按 2 字节对齐，因此键的最低位总是 1，
+//!   不会与以上几种取值冲突
+//!
+//! 释放的表项不保留墓碑，因此查找不能在遇到 [`EMPTY`] 时结束。登记表记录插入时探测的最远距离，
+//! 查找最多探测这么远。插入总是使用探测序列中第一个空闲的表项，
+//! 所以最远距离只取决于同时登记的块的数量，不会随着分配和释放的次数增长
+
+use core::alloc::Layout;
+use core::mem::size_of;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::get_data_base;
+
+const EMPTY: usize = 0;
+const BUSY: usize = 2;
+const KEY_FLAG: usize = 1;
+
+/// 一个尚未释放的块
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Allocation {
+    /// 块的实际地址
+    pub addr: usize,
+    /// 分配时使用的 layout
+    pub layout: Layout,
+    /// 分配时传入的 tag，`alloc_` 传入 0
+    pub tag: usize,
+}
+
+struct Entry {
+    key: AtomicUsize,
+    size: AtomicUsize,
+    align: AtomicUsize,
+    tag: AtomicUsize,
+}
+
+/// 每个表项的大小
+const ENTRY_SIZE: usize = size_of::<Entry>();
+
+pub(crate) struct LeakRegistry {
+    /// 表的偏移量
+    table: AtomicUsize,
+    /// 表项数量，为 0 代表没有初始化
+    capacity: AtomicUsize,
+    /// 因为表满而没有登记的块的数量
+    overflow: AtomicUsize,
+    /// 已登记的块距离探测序列起点的最远距离
+    max_probe: AtomicUsize,
+}
+
+impl LeakRegistry {
+    pub(crate) const fn new() -> Self {
+        Self {
+            table: AtomicUsize::new(0),
+            capacity: AtomicUsize::new(0),
+            overflow: AtomicUsize::new(0),
+            max_probe: AtomicUsize::new(0),
+        }
+    }
+
+    /// 使用 [start, start + size) 存储登记表
+    /// SAFETY: 该内存区域需要有效、按 usize 对齐，且不能与堆的区域重叠；调用时不能有并发的分配和释放
+    pub(crate) unsafe fn init(&self, start: usize, size: usize) {
+        assert!(start.is_multiple_of(core::mem::align_of::<Entry>()));
+        let capacity = size / ENTRY_SIZE;
+        core::ptr::write_bytes(start as *mut u8, 0, capacity * ENTRY_SIZE);
+        self.table
+            .store(start.wrapping_sub(get_data_base()), Ordering::Relaxed);
+        self.overflow.store(0, Ordering::Relaxed);
+        self.max_probe.store(0, Ordering::Relaxed);
+        self.capacity.store(capacity, Ordering::Release);
+    }
+
+    fn entries(&self) -> &[Entry] {
+        let capacity = self.capacity.load(Ordering::Acquire);
+        if capacity == 0 {
+            return &[];
+        }
+        let table = self
+            .table
+            .load(Ordering::Relaxed)
+            .wrapping_add(get_data_base());
+        unsafe { core::slice::from_raw_parts(table as *const Entry, capacity) }
+    }
+
+    /// 探测序列的起点
+    fn start_index(key: usize, capacity: usize) -> usize {
+        const MULTIPLIER: usize = 0x9e37_79b9_7f4a_7c15_u64 as usize;
+        (key.wrapping_mul(MULTIPLIER) >> 8) % capacity
+    }
+
+    fn key(addr: usize) -> usize {
+        addr.wrapping_sub(get_data_base()) | KEY_FLAG
+    }
+
+    /// 登记 addr 处的块
+    pub(crate) fn insert(&self, addr: usize, layout: Layout, tag: usize) {
+        let entries = self.entries();
+        if entries.is_empty() {
+            return;
+        }
+        let key = Self::key(addr);
+        let start = Self::start_index(key, entries.len());
+        for i in 0..entries.len() {
+            let entry = &entries[(start + i) % entries.len()];
+            let current = entry.key.load(Ordering::Relaxed);
+            if current == EMPTY
+                && entry
+                    .key
+                    .compare_exchange(current, BUSY, Ordering::Acquire, Ordering::Relaxed)
+                    .is_ok()
+            {
+                entry.size.store(layout.size(), Ordering::Relaxed);
+                entry.align.store(layout.align(), Ordering::Relaxed);
+                entry.tag.store(tag, Ordering::Relaxed);
+                // Release：读者看到键后，一定能看到表项的其它字段
+                entry.key.store(key, Ordering::Release);
+                // 块被释放之前，释放它的线程一定能看到这次更新
+                self.max_probe.fetch_max(i, Ordering::Relaxed);
+                return;
+            }
+        }
+        self.overflow.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// 注销 addr 处的块。块没有登记（例如表满时分配的块）时不做任何事
+    pub(crate) fn remove(&self, addr: usize) {
+        let entries = self.entries();
+        if entries.is_empty() {
+            return;
+        }
+        let key = Self::key(addr);
+        let start = Self::start_index(key, entries.len());
+        for i in 0..=self.max_probe() {
+            let entry = &entries[(start + i) % entries.len()];
+            if entry.key.load(Ordering::Relaxed) == key {
+                entry.key.store(EMPTY, Ordering::Release);
+                return;
+            }
+        }
+    }
+
+    pub(crate) fn iter(&self) -> Leaks<'_> {
+        Leaks {
+            entries: self.entries().iter(),
+            base: get_data_base(),
+        }
+    }
+
+    /// 查找最多探测的表项数减一
+    pub(crate) fn max_probe(&self) -> usize {
+        self.max_probe.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn overflow(&self) -> usize {
+        self.overflow.load(Ordering::Relaxed)
+    }
+}
+
+/// 遍历所有已登记（即尚未释放）的块，由 `LockFreeHeap::leaks` 返回
+/// 与分配和释放并发时，结果不一定对应某一时刻的准确状态
+pub struct Leaks<'a> {
+    entries: core::slice::Iter<'a, Entry>,
+    base: usize,
+}
+
+impl Iterator for Leaks<'_> {
+    type Item = Allocation;
+
+    fn next(&mut self) -> Option<Allocation> {
+        for entry in self.entries.by_ref() {
+            let key = entry.key.load(Ordering::Acquire);
+            if key & KEY_FLAG == 0 {
+                continue;
+            }
+            let size = entry.size.load(Ordering::Relaxed);
+            let align = entry.align.load(Ordering::Relaxed);
+            return Some(Allocation {
+                addr: (key & !KEY_FLAG).wrapping_add(self.base),
+                layout: unsafe { Layout::from_size_align_unchecked(size, align) },
+                tag: entry.tag.load(Ordering::Relaxed),
+            });
+        }
+        None
+    }
+}
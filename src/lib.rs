@@ -4,12 +4,67 @@
 //! heap_tests.rs 中对无锁堆分配器进行了简单的测试，没有进行大规模并发测试
 #![cfg_attr(not(test), no_std)]
 
+#[macro_use]
+mod sync;
+
+#[cfg(feature = "alloc-bitmap")]
+mod alloc_bitmap;
+mod cache_padded;
+mod dump;
+#[cfg(any(feature = "alloc-bitmap", feature = "poison"))]
+mod error;
+#[cfg(feature = "fault-injection")]
+mod fault;
+#[cfg(feature = "heap-grow")]
+mod grow;
+#[cfg(feature = "alloc-hooks")]
+mod hooks;
 mod imp;
+#[cfg(feature = "leak-registry")]
+mod leak_registry;
 mod linked_list;
+#[cfg(feature = "percpu-stats")]
+mod percpu;
+#[cfg(feature = "poison")]
+mod poison;
+mod quota;
+mod region;
+#[cfg(feature = "heap-release")]
+mod release;
+mod render;
+mod reserve;
+mod sharded;
+mod static_heap;
+mod stats;
+mod verify;
+#[cfg(feature = "watermarks")]
+mod watermark;
+pub use dump::{DumpError, DumpRegion, DumpStats, HeapDump};
+#[cfg(any(feature = "alloc-bitmap", feature = "poison"))]
+pub use error::{HeapError, HeapErrorHandler};
+#[cfg(feature = "fault-injection")]
+pub use fault::{FaultInjector, FaultPoint};
+#[cfg(feature = "heap-grow")]
+pub use grow::HeapGrow;
+#[cfg(feature = "alloc-hooks")]
+pub use hooks::{AllocHook, HeapEvent};
 pub use imp::LockFreeHeap;
+#[cfg(feature = "leak-registry")]
+pub use leak_registry::{Allocation, Leaks};
 pub use linked_list::LinkedList;
+#[cfg(feature = "percpu-stats")]
+pub use percpu::CpuHint;
+pub use quota::{QuotaError, QuotaHeap};
+#[cfg(feature = "heap-release")]
+pub use release::HeapRelease;
+pub use reserve::Reservation;
+pub use sharded::ShardedHeap;
+pub use static_heap::StaticHeap;
+pub use verify::Violation;
+#[cfg(feature = "watermarks")]
+pub use watermark::{MemoryPressure, Pressure};
 
-#[cfg(test)]
+#[cfg(all(test, not(any(loom, shuttle))))]
 mod list_tests;
 
 /// SAFETY:
@@ -18,9 +73,33 @@ mod list_tests;
 /// 但是 `get_data_base` 是使用一个全局变量来存储数据段的基地址，
 /// 在进行不同的测试时，可能会导致出错，因此需要单独对每个函数进行测试，而不是整体测试
 /// 或者传递参数 `--test-threads=1`
-#[cfg(test)]
+#[cfg(all(test, not(any(loom, shuttle))))]
 mod heap_tests;
 
+/// 与参考 buddy 分配器的差分测试
+/// 分配位图占用区域开头的空间，参考模型没有模拟这一点
+#[cfg(all(test, not(any(loom, shuttle)), not(feature = "alloc-bitmap")))]
+mod model_tests;
+
+/// 故障注入测试，运行方式：
+/// `cargo test --lib --features fault-injection fault_tests`
+#[cfg(all(test, feature = "fault-injection", not(any(loom, shuttle))))]
+mod fault_tests;
+
+/// 链表的线性一致性压力测试
+#[cfg(all(test, not(any(loom, shuttle))))]
+mod linearizability_tests;
+
+/// loom 模型检查，运行方式：
+/// `RUSTFLAGS="--cfg loom" cargo test --release --lib loom_tests`
+#[cfg(all(test, loom))]
+mod loom_tests;
+
+/// shuttle 随机调度测试，运行方式：
+/// `RUSTFLAGS="--cfg shuttle" cargo test --release --lib shuttle_tests`
+#[cfg(all(test, shuttle))]
+mod shuttle_tests;
+
 pub fn get_data_base() -> usize {
     crate_interface::call_interface!(pi_pointer::GetDataBase::get_data_base)
 }
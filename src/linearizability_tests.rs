@@ -0,0 +1,264 @@
+//! 链表的线性一致性测试
+//!
+//! 多个线程并发地对链表执行随机的 push、pop、delete，记录每个操作的调用时刻、返回时刻和结果，
+//! 再用 Wing–Gong 算法检查这段历史能否按某个与实时顺序一致的全序，
+//! 在顺序规格（栈，delete 可以删除任意位置的元素）上得到相同的结果。
+//! 检查失败时，逐个去掉不影响结果的操作，报告一段最小的违反线性一致性的历史。
+
+use std::collections::HashSet;
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Barrier;
+use std::thread;
+
+use rand::{Rng, SeedableRng};
+
+use crate::LinkedList;
+
+/// 操作及其参数，节点以其在节点池中的下标表示
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+enum Call {
+    Push(usize),
+    Pop,
+    Delete(usize),
+}
+
+/// 操作的结果
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+enum Ret {
+    Push,
+    Pop(Option<usize>),
+    Delete(bool),
+}
+
+/// 历史中的一个操作，时刻为全局逻辑时钟的值
+#[derive(Clone, Copy, Debug)]
+struct Operation {
+    thread: usize,
+    call: Call,
+    ret: Ret,
+    invoke: usize,
+    response: usize,
+}
+
+impl Operation {
+    /// 操作涉及的节点
+    fn node(&self) -> Option<usize> {
+        match (self.call, self.ret) {
+            (Call::Push(node) | Call::Delete(node), _) => Some(node),
+            (_, Ret::Pop(node)) => node,
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "thread {} [{:>3}, {:>3}] ",
+            self.thread, self.invoke, self.response
+        )?;
+        match (self.call, self.ret) {
+            (Call::Push(node), _) => write!(f, "push({})", node),
+            (Call::Pop, Ret::Pop(node)) => write!(f, "pop() -> {:?}", node),
+            (Call::Delete(node), Ret::Delete(found)) => write!(f, "delete({}) -> {}", node, found),
+            (call, ret) => write!(f, "{:?} -> {:?}", call, ret),
+        }
+    }
+}
+
+/// 顺序规格：栈顶位于末尾
+fn apply(state: &[usize], call: Call) -> (Vec<usize>, Ret) {
+    let mut next = state.to_vec();
+    let ret = match call {
+        Call::Push(node) => {
+            next.push(node);
+            Ret::Push
+        }
+        Call::Pop => Ret::Pop(next.pop()),
+        Call::Delete(node) => match next.iter().position(|&n| n == node) {
+            Some(i) => {
+                next.remove(i);
+                Ret::Delete(true)
+            }
+            None => Ret::Delete(false),
+        },
+    };
+    (next, ret)
+}
+
+/// Wing–Gong 算法，记录已经搜索过的（剩余操作，状态）以避免重复搜索
+fn linearizable(history: &[Operation]) -> bool {
+    fn search(
+        history: &[Operation],
+        remaining: u64,
+        state: Vec<usize>,
+        seen: &mut HashSet<(u64, Vec<usize>)>,
+    ) -> bool {
+        if remaining == 0 {
+            return true;
+        }
+        if !seen.insert((remaining, state.clone())) {
+            return false;
+        }
+        let pending = || (0..history.len()).filter(move |i| remaining & (1 << i) != 0);
+        // 只有在最早的返回时刻之前调用的操作，才可能是下一个被线性化的操作
+        let first_response = pending().map(|i| history[i].response).min().unwrap();
+        pending()
+            .filter(|&i| history[i].invoke < first_response)
+            .any(|i| {
+                let (next, ret) = apply(&state, history[i].call);
+                ret == history[i].ret && search(history, remaining & !(1 << i), next, seen)
+            })
+    }
+
+    assert!(history.len() < 64);
+    let all = (1 << history.len()) - 1;
+    search(history, all, Vec::new(), &mut HashSet::new())
+}
+
+/// 逐个去掉不影响检查结果的操作，返回一段仍然违反线性一致性的最小历史。
+/// 去掉 push 时一并去掉涉及同一节点的操作，以免只剩下凭空出现的节点
+fn minimize(history: &[Operation]) -> Vec<Operation> {
+    let mut history = history.to_vec();
+    let mut i = 0;
+    while i < history.len() {
+        let mut smaller = history.clone();
+        let removed = smaller.remove(i);
+        if let Call::Push(node) = removed.call {
+            smaller.retain(|op| op.node() != Some(node));
+        }
+        if linearizable(&smaller) {
+            i += 1;
+        } else {
+            history = smaller;
+        }
+    }
+    history
+}
+
+fn check(history: &[Operation]) {
+    if linearizable(history) {
+        return;
+    }
+    let mut minimal = minimize(history);
+    minimal.sort_by_key(|op| op.invoke);
+    let lines: Vec<String> = minimal.iter().map(|op| op.to_string()).collect();
+    panic!(
+        "history of {} operations is not linearizable, minimal violating history:\n{}",
+        history.len(),
+        lines.join("\n")
+    );
+}
+
+#[test]
+fn test_checker() {
+    let op = |thread, call, ret, invoke, response| Operation {
+        thread,
+        call,
+        ret,
+        invoke,
+        response,
+    };
+
+    // 并发的 push 与 pop，pop 可以在 push 之前或之后被线性化
+    let history = [
+        op(0, Call::Push(0), Ret::Push, 0, 3),
+        op(1, Call::Pop, Ret::Pop(Some(0)), 1, 4),
+        op(2, Call::Pop, Ret::Pop(None), 2, 5),
+    ];
+    assert!(linearizable(&history));
+
+    // push 返回之后调用的 pop 不能返回 None
+    let history = [
+        op(0, Call::Push(0), Ret::Push, 0, 1),
+        op(1, Call::Delete(1), Ret::Delete(false), 0, 5),
+        op(1, Call::Pop, Ret::Pop(None), 2, 3),
+    ];
+    assert!(!linearizable(&history));
+    let minimal = minimize(&history);
+    assert_eq!(minimal.len(), 2);
+    assert_eq!(minimal[0].call, Call::Push(0));
+    assert_eq!(minimal[1].call, Call::Pop);
+
+    // 同一个节点不能被删除两次
+    let history = [
+        op(0, Call::Push(0), Ret::Push, 0, 1),
+        op(0, Call::Delete(0), Ret::Delete(true), 2, 4),
+        op(1, Call::Delete(0), Ret::Delete(true), 3, 5),
+    ];
+    assert!(!linearizable(&history));
+}
+
+#[test]
+fn test_linked_list_linearizable() {
+    // 重复测试的次数，每次使用新的链表
+    const TEST_NUM: usize = 2000;
+    const NUM_THREADS: usize = 4;
+    const NUM_OPS_PER_THREAD: usize = 8;
+    // 每个线程只 push 自己的节点，每个节点最多 push 一次，避免节点被复用
+    const NUM_NODES_PER_THREAD: usize = 4;
+    const NUM_NODES: usize = NUM_THREADS * NUM_NODES_PER_THREAD;
+
+    for current_num in 0..TEST_NUM {
+        let mut nodes = [[0usize; 2]; NUM_NODES];
+        let base = nodes.as_mut_ptr() as usize;
+        let node_ptr = |node: usize| (base + node * size_of::<[usize; 2]>()) as *mut ();
+        let node_index = |ptr: *mut ()| (ptr as usize - base) / size_of::<[usize; 2]>();
+
+        let list = LinkedList::new();
+        #[cfg(feature = "debug-checks")]
+        list.set_node_bounds(base, node_ptr(NUM_NODES) as usize);
+        let clock = AtomicUsize::new(0);
+        let barrier = Barrier::new(NUM_THREADS);
+
+        let history: Vec<Operation> = thread::scope(|s| {
+            let handles: Vec<_> = (0..NUM_THREADS)
+                .map(|thread| {
+                    let (list, clock, barrier) = (&list, &clock, &barrier);
+                    s.spawn(move || {
+                        let mut rng = rand_chacha::ChaCha8Rng::seed_from_u64(
+                            (current_num * NUM_THREADS + thread) as u64,
+                        );
+                        let mut own = (0..NUM_NODES_PER_THREAD)
+                            .map(|i| thread * NUM_NODES_PER_THREAD + i)
+                            .peekable();
+                        let mut ops = Vec::with_capacity(NUM_OPS_PER_THREAD);
+                        barrier.wait();
+                        for _ in 0..NUM_OPS_PER_THREAD {
+                            let call = match rng.random_range(0..3) {
+                                0 if own.peek().is_some() => Call::Push(own.next().unwrap()),
+                                1 => Call::Delete(rng.random_range(0..NUM_NODES)),
+                                _ => Call::Pop,
+                            };
+                            let invoke = clock.fetch_add(1, Ordering::SeqCst);
+                            let ret = match call {
+                                Call::Push(node) => {
+                                    unsafe { list.push(node_ptr(node)) };
+                                    Ret::Push
+                                }
+                                Call::Pop => Ret::Pop(list.pop().map(node_index)),
+                                Call::Delete(node) => Ret::Delete(list.delete(node_ptr(node))),
+                            };
+                            let response = clock.fetch_add(1, Ordering::SeqCst);
+                            ops.push(Operation {
+                                thread,
+                                call,
+                                ret,
+                                invoke,
+                                response,
+                            });
+                        }
+                        ops
+                    })
+                })
+                .collect();
+            handles
+                .into_iter()
+                .flat_map(|handle| handle.join().unwrap())
+                .collect()
+        });
+        check(&history);
+    }
+}
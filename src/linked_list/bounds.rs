@@ -0,0 +1,38 @@
+use pi_pointer::NULL_PTR;
+
+use crate::region::RegionTable;
+
+/// 链表节点地址的合法范围，用于 debug
+/// 每个范围对应堆中的一个区域，以区域表的形式记录，因此不相邻的区域之间的空隙也会被检查。
+/// 未设置范围时不进行检查。
+pub(crate) struct NodeBounds {
+    ranges: RegionTable,
+}
+
+impl NodeBounds {
+    pub(crate) const fn new() -> Self {
+        Self {
+            ranges: RegionTable::new(),
+        }
+    }
+
+    /// 将 [start, end) 加入合法范围，参数为实际地址
+    /// 可以与其它操作并发调用，范围记录完成之后才会用于检查
+    pub(crate) fn add(&self, start: usize, end: usize) {
+        assert!(
+            self.ranges.register(start, start, end),
+            "too many list node ranges"
+        );
+    }
+
+    /// 检查节点的实际地址是否在某个范围内，NULL_PTR 总是合法的
+    pub(crate) fn check(&self, node: *mut ()) {
+        let node = node as usize;
+        if node == NULL_PTR || self.ranges.find(node).is_some() {
+            return;
+        }
+        if self.ranges.iter().next().is_some() {
+            panic!("list node {:#x} out of bounds", node);
+        }
+    }
+}
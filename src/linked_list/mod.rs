@@ -1,24 +1,48 @@
-use core::{
-    hint::spin_loop,
-    sync::atomic::{AtomicPtr, AtomicUsize, Ordering},
-};
+use crate::sync::spin_loop;
 
 /// 位置无关的无锁侵入式链表
-use node_ptr::{ListNode, MarkedPtr, NodePtr};
+use node_ptr::{MarkedPtr, NodePtr};
 use pi_pointer::{PIPtr, WrappedPtr};
 
+#[cfg(feature = "debug-checks")]
+mod bounds;
 #[allow(unused)]
 mod node_ptr;
+#[cfg(feature = "tagged-head")]
+mod tagged;
+
+#[cfg(feature = "debug-checks")]
+use bounds::NodeBounds;
+#[cfg(feature = "node-canary")]
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// 检查节点的实际地址是否在链表的合法范围内
+/// 未启用 `debug-checks` 时展开为空，参数不会被求值
+macro_rules! check_node {
+    ($list:expr, $node:expr) => {
+        #[cfg(feature = "debug-checks")]
+        $list.bounds.check($node);
+    };
+}
+
+/// 在读取节点的指针之前检查节点的校验值，校验值被破坏时 panic
+/// 未启用 `node-canary` 时展开为空，参数不会被求值
+macro_rules! check_canary {
+    ($list:expr, $node:expr) => {
+        #[cfg(feature = "node-canary")]
+        $list.check_canary($node);
+    };
+}
 
 /// 用于测试
 #[allow(unused_imports)]
-pub(crate) use node_ptr::DELETE_MARK;
+pub(crate) use node_ptr::{untag, DELETE_MARK};
+
+/// loom 的原子类型需要在模型内构造，因此 loom 测试需要预先构造节点
+pub(crate) use node_ptr::ListNode;
 
-/// 用于debug
-/// 将链表节点的指针限制在[NODE_LBOUND, NODE_UBOUND)范围内
-/// 因此链表节点的取值也限制在[NODE_LBOUND, NODE_UBOUND)∪{NULL_PTR, NULL_PTR | DELETE_MARK}范围内。
-pub(crate) static NODE_UBOUND: AtomicPtr<()> = AtomicPtr::new(0 as *mut ());
-pub(crate) static NODE_LBOUND: AtomicPtr<()> = AtomicPtr::new(0 as *mut ());
+/// 链表节点的大小，每个空闲块的开头需要能放下一个链表节点
+pub(crate) const NODE_SIZE: usize = core::mem::size_of::<ListNode>();
 
 /// An intrusive linked list
 ///
@@ -35,47 +59,150 @@ pub(crate) static NODE_LBOUND: AtomicPtr<()> = AtomicPtr::new(0 as *mut ());
 pub struct LinkedList {
     /// 为了接近论文中的链表结构，将head也实现为节点。
     head: ListNode,
+    /// 用于debug
+    /// 将链表节点的指针限制在加入堆的各个区域内
+    /// 因此链表节点的取值也限制在这些区域∪{NULL_PTR, NULL_PTR | DELETE_MARK}范围内。
+    #[cfg(feature = "debug-checks")]
+    bounds: NodeBounds,
+    /// 链表中块大小的以 2 为底的对数，只用于校验失败时的诊断信息，usize::MAX 代表未知
+    #[cfg(feature = "node-canary")]
+    order: AtomicUsize,
 }
 
 unsafe impl Send for LinkedList {}
 unsafe impl Sync for LinkedList {}
 
 impl LinkedList {
+    #[cfg(not(any(loom, shuttle)))]
     pub(crate) const EMPTY_LIST: Self = Self::new();
 
-    /// Create a new LinkedList
-    pub const fn new() -> LinkedList {
-        LinkedList {
-            head: ListNode::null(),
+    const_fn! {
+        /// Create a new LinkedList
+        pub fn new() -> LinkedList {
+            LinkedList {
+                head: ListNode::null(),
+                #[cfg(feature = "debug-checks")]
+                bounds: NodeBounds::new(),
+                #[cfg(feature = "node-canary")]
+                order: AtomicUsize::new(usize::MAX),
+            }
+        }
+    }
+
+    /// 将链表节点限制在 [start, end) 范围内，超出范围时 panic
+    /// 仅在启用 `debug-checks` 时存在
+    #[cfg(feature = "debug-checks")]
+    pub fn set_node_bounds(&self, start: usize, end: usize) {
+        self.bounds.add(start, end);
+    }
+
+    /// 将 [start, end) 加入链表节点的合法范围，节点可以位于任意一个已加入的范围内
+    #[cfg(feature = "debug-checks")]
+    pub(crate) fn add_node_bounds(&self, start: usize, end: usize) {
+        self.bounds.add(start, end);
+    }
+
+    /// 记录链表中块的大小，用于校验失败时的诊断信息
+    #[cfg(feature = "node-canary")]
+    pub(crate) fn set_order(&self, order: usize) {
+        self.order.store(order, Ordering::Relaxed);
+    }
+
+    /// 检查节点的校验值以及节点指针的校验值
+    #[cfg(feature = "node-canary")]
+    fn check_canary(&self, node: &NodePtr) {
+        let Some(node) = node.pointed_node() else {
+            return;
+        };
+        let (field, found, expected) = match (node.canary(), node.expected_canary()) {
+            (found, expected) if found != expected => ("canary", found, expected),
+            _ => match node.ptr_checksum() {
+                (found, expected) if found != expected => ("pointer", found, expected),
+                _ => return,
+            },
+        };
+        let addr = node as *const ListNode as usize;
+        match self.order.load(Ordering::Relaxed) {
+            usize::MAX => panic!(
+                "list node {} corrupted at block {:#x}: found {:#x}, expected {:#x}",
+                field, addr, found, expected
+            ),
+            order => panic!(
+                "list node {} corrupted at block {:#x} (order {}): found {:#x}, expected {:#x}",
+                field, addr, order, found, expected
+            ),
         }
     }
 
+    /// 链表头包括版本号在内的完整值，用于测试
+    #[cfg(all(test, feature = "tagged-head"))]
+    pub(crate) fn head_raw(&self) -> *mut () {
+        self.head.load_raw()
+    }
+
+    /// 若链表头的完整值仍为 snapshot，则将其修改为位置无关地址 new，与 push 中的 CAS 相同，用于测试
+    #[cfg(all(test, feature = "tagged-head"))]
+    pub(crate) fn cas_head_snapshot(&self, snapshot: *mut (), new: *mut ()) -> bool {
+        tagged::cas_snapshot(&self.head, snapshot, new)
+    }
+
     /// Return `true` if the list is empty
     pub fn is_empty(&self) -> bool {
         let (_, right_node) = self.get_headptr_head();
         return right_node.is_null();
     }
 
+    /// item 是否在链表中且未被标记
+    pub(crate) fn contains(&self, item: *mut ()) -> bool {
+        let (_, right_node) = self.search_with_ptr(item);
+        !right_node.is_null() && right_node.ptr() == item
+    }
+
+    /// 按顺序对链表中每个未被标记的节点调用 f，参数为节点的实际地址
+    /// 与其它操作并发时，可能遗漏或重复访问正在被插入或删除的节点
+    pub(crate) fn for_each_node(&self, mut f: impl FnMut(*mut ())) {
+        let mut t = self.head.marked_ptr();
+        loop {
+            check_canary!(self, &t);
+            let Some(t_next) = t.next() else {
+                break;
+            };
+            check_node!(self, t_next.ptr());
+            if !t_next.is_marked() {
+                f(t.ptr());
+            }
+            t = NodePtr::from_value(t_next.unmark());
+        }
+    }
+
     /// Push `item` to the front of the list
     /// SAFETY: item需要指向一个有效的、大小至少16字节的内存地址
     pub unsafe fn push(&self, item: *mut ()) {
-        assert!((item as usize) >= (NODE_LBOUND.load(Ordering::SeqCst) as usize));
-        assert!((item as usize) < (NODE_UBOUND.load(Ordering::SeqCst) as usize));
-        let rc: &AtomicUsize = unsafe { &*(item as *mut AtomicUsize).add(1) };
-        rc.store(0, Ordering::SeqCst);
+        check_node!(self, item);
+        unsafe { ListNode::from_its_ptr(item) }.rc_reset();
         let new_node = NodePtr::from_value(item);
         loop {
+            // 在查找前记录链表头的完整值，以检测查找期间链表头的变化
+            #[cfg(feature = "tagged-head")]
+            let snapshot = self.head.load_raw();
             let (left_node, right_node) = self.get_headptr_head();
             new_node
                 .pointed_node()
                 .unwrap()
                 .store(right_node.linked_value());
-            if left_node
-                .pointed_node()
-                .unwrap()
-                .compare_exchange(right_node.linked_value(), new_node.linked_value())
-                .is_ok()
-            {
+            // get_headptr_head 返回的 left_node 总是链表头
+            #[cfg(feature = "tagged-head")]
+            debug_assert!(core::ptr::eq(left_node.pointed_node().unwrap(), &self.head));
+            #[cfg(feature = "tagged-head")]
+            let success = tagged::untag(snapshot) == right_node.linked_value()
+                && tagged::cas_snapshot(&self.head, snapshot, new_node.linked_value());
+            #[cfg(not(feature = "tagged-head"))]
+            let success = self.cas_next(
+                &left_node,
+                right_node.linked_value(),
+                new_node.linked_value(),
+            );
+            if success {
                 return;
             }
         }
@@ -95,16 +222,7 @@ impl LinkedList {
             }
             right_node_value = right_node.pointed_node().unwrap().load(); // 位置无关，但可能有标记
             if !right_node_value.is_marked() {
-                if !right_node_value.is_null() {
-                    assert!(
-                        (right_node_value.value() as usize)
-                            >= (NODE_LBOUND.load(Ordering::SeqCst) as usize)
-                    );
-                    assert!(
-                        (right_node_value.value() as usize)
-                            < (NODE_UBOUND.load(Ordering::SeqCst) as usize)
-                    );
-                }
+                check_node!(self, right_node_value.ptr());
                 if right_node
                     .pointed_node()
                     .unwrap()
@@ -117,22 +235,16 @@ impl LinkedList {
                 }
             }
         }
+        fault_point!(crate::fault::FaultPoint::PopMarked {
+            node: right_node.ptr() as usize
+        });
         // 物理删除
-        if !right_node_value.is_null() {
-            assert!(
-                (right_node_value.value() as usize)
-                    >= (NODE_LBOUND.load(Ordering::SeqCst) as usize)
-            );
-            assert!(
-                (right_node_value.value() as usize) < (NODE_UBOUND.load(Ordering::SeqCst) as usize)
-            );
-        }
-        if left_node
-            .pointed_node()
-            .unwrap()
-            .compare_exchange(right_node.linked_value(), right_node_value.value())
-            .is_err()
-        {
+        check_node!(self, right_node_value.ptr());
+        if !self.cas_next(
+            &left_node,
+            right_node.linked_value(),
+            right_node_value.value(),
+        ) {
             let (_, new_right_node) = self.search_with_ptr(right_node.ptr());
             // 验证right_node已从链表中删去，即以right_node从链表中搜索到的节点不是right_node
             assert!(new_right_node.ptr() != right_node.ptr());
@@ -160,21 +272,13 @@ impl LinkedList {
         // 查找与逻辑删除
         loop {
             (left_node, right_node) = self.search_with_ptr(item);
-            if right_node.is_null() {
+            // 所找项已被其它线程标记时，search_with_ptr 返回的是其后第一个未标记的节点
+            if right_node.is_null() || right_node.ptr() != item {
                 return false;
             }
             right_node_value = right_node.pointed_node().unwrap().load(); // 位置无关，但可能有标记
             if !right_node_value.is_marked() {
-                if !right_node_value.is_null() {
-                    assert!(
-                        (right_node_value.value() as usize)
-                            >= (NODE_LBOUND.load(Ordering::SeqCst) as usize)
-                    );
-                    assert!(
-                        (right_node_value.value() as usize)
-                            < (NODE_UBOUND.load(Ordering::SeqCst) as usize)
-                    );
-                }
+                check_node!(self, right_node_value.ptr());
                 if right_node
                     .pointed_node()
                     .unwrap()
@@ -187,22 +291,16 @@ impl LinkedList {
                 }
             }
         }
+        fault_point!(crate::fault::FaultPoint::DeleteMarked {
+            node: right_node.ptr() as usize
+        });
         // 物理删除
-        if !right_node_value.is_null() {
-            assert!(
-                (right_node_value.value() as usize)
-                    >= (NODE_LBOUND.load(Ordering::SeqCst) as usize)
-            );
-            assert!(
-                (right_node_value.value() as usize) < (NODE_UBOUND.load(Ordering::SeqCst) as usize)
-            );
-        }
-        if left_node
-            .pointed_node()
-            .unwrap()
-            .compare_exchange(right_node.linked_value(), right_node_value.value())
-            .is_err()
-        {
+        check_node!(self, right_node_value.ptr());
+        if !self.cas_next(
+            &left_node,
+            right_node.linked_value(),
+            right_node_value.value(),
+        ) {
             let (_, new_right_node) = self.search_with_ptr(right_node.ptr());
             // 验证right_node已从链表中删去，即以right_node从链表中搜索到的节点不是right_node
             assert!(new_right_node.ptr() != right_node.ptr());
@@ -220,6 +318,17 @@ impl LinkedList {
 
 // private函数
 impl LinkedList {
+    /// 若 left_node 的值为 current，则将其修改为 new
+    /// left_node 可能是链表头，启用 `tagged-head` 时需要同时增加链表头的版本号
+    fn cas_next(&self, left_node: &NodePtr, current: *mut (), new: *mut ()) -> bool {
+        let node = left_node.pointed_node().unwrap();
+        #[cfg(feature = "tagged-head")]
+        if core::ptr::eq(node, &self.head) {
+            return tagged::cas(node, current, new);
+        }
+        node.compare_exchange(current, new).is_ok()
+    }
+
     pub(crate) fn search_with_ptr(&self, item: *mut ()) -> (NodePtr, NodePtr) {
         // 两个返回值分别为left_node和right_node
         let mut left_node: NodePtr = NodePtr::null();
@@ -242,7 +351,9 @@ impl LinkedList {
                     if t.is_null() {
                         break;
                     }
+                    check_canary!(self, &t);
                     t_next = t.next().unwrap();
+                    check_node!(self, t_next.ptr());
                     if t.ptr() == item {
                         found = true;
                     }
@@ -254,6 +365,11 @@ impl LinkedList {
                 }
                 right_node = t;
                 drop(t_next);
+                fault_point!(crate::fault::FaultPoint::Search {
+                    item: item as usize,
+                    left: left_node.ptr() as usize,
+                    right: right_node.ptr() as usize,
+                });
 
                 /* 2: Check nodes are adjacent*/
                 if left_node_next.value() == right_node.value() {
@@ -265,22 +381,12 @@ impl LinkedList {
                 }
 
                 /* 3: Remove one or more marked nodes */
-                if !right_node.is_null() {
-                    assert!(
-                        (right_node.value() as usize)
-                            >= (NODE_LBOUND.load(Ordering::SeqCst) as usize)
-                    );
-                    assert!(
-                        (right_node.value() as usize)
-                            < (NODE_UBOUND.load(Ordering::SeqCst) as usize)
-                    );
-                }
-                if left_node
-                    .pointed_node()
-                    .unwrap()
-                    .compare_exchange(left_node_next.linked_value(), right_node.linked_value())
-                    .is_ok()
-                {
+                check_node!(self, right_node.ptr());
+                if self.cas_next(
+                    &left_node,
+                    left_node_next.linked_value(),
+                    right_node.linked_value(),
+                ) {
                     if !right_node.is_null() && right_node.pointed_node().unwrap().is_marked() {
                         break;
                     } else {
@@ -312,7 +418,9 @@ impl LinkedList {
                     if t.is_null() {
                         break;
                     }
+                    check_canary!(self, &t);
                     t_next = t.next().unwrap();
+                    check_node!(self, t_next.ptr());
                     // rust没有do-while，因此这样退出循环
                     if !t_next.is_marked() {
                         break;
@@ -331,22 +439,12 @@ impl LinkedList {
                 }
 
                 /* 3: Remove one or more marked nodes */
-                if !right_node.is_null() {
-                    assert!(
-                        (right_node.value() as usize)
-                            >= (NODE_LBOUND.load(Ordering::SeqCst) as usize)
-                    );
-                    assert!(
-                        (right_node.value() as usize)
-                            < (NODE_UBOUND.load(Ordering::SeqCst) as usize)
-                    );
-                }
-                if left_node
-                    .pointed_node()
-                    .unwrap()
-                    .compare_exchange(left_node_next.linked_value(), right_node.linked_value())
-                    .is_ok()
-                {
+                check_node!(self, right_node.ptr());
+                if self.cas_next(
+                    &left_node,
+                    left_node_next.linked_value(),
+                    right_node.linked_value(),
+                ) {
                     if !right_node.is_null() && right_node.pointed_node().unwrap().is_marked() {
                         break;
                     } else {
@@ -1,8 +1,43 @@
-use core::sync::atomic::{AtomicPtr, AtomicUsize, Ordering};
+use pi_pointer::{PIPtr, WrappedPtr, NULL_PTR};
 
-use pi_pointer::{AtomicWrappedPtr, PIPtr, WrappedPtr, NULL_PTR};
+use crate::sync::{AtomicPtr, AtomicUsize, Ordering};
 
-use crate::linked_list::{NODE_LBOUND, NODE_UBOUND};
+#[cfg(feature = "node-canary")]
+use crate::get_data_base;
+
+/// 启用 `tagged-head` 或 `node-canary` 时，节点的值的高 16 位分别用作链表头的版本号或其余节点的校验值，
+/// 低 48 位存储位置无关地址，读取时进行符号扩展
+#[cfg(any(feature = "tagged-head", feature = "node-canary"))]
+pub(crate) const VALUE_BITS: u32 = 48;
+
+/// 去掉版本号或校验值，得到可能带有删除标记的位置无关地址
+#[cfg(any(feature = "tagged-head", feature = "node-canary"))]
+pub(crate) fn untag(raw: *mut ()) -> *mut () {
+    (((raw as usize) << (usize::BITS - VALUE_BITS)) as isize >> (usize::BITS - VALUE_BITS))
+        as *mut ()
+}
+
+/// 节点的值不带版本号和校验值
+#[cfg(not(any(feature = "tagged-head", feature = "node-canary")))]
+pub(crate) fn untag(raw: *mut ()) -> *mut () {
+    raw
+}
+
+/// 启用 `node-canary` 时，在值的高 16 位写入由低 48 位计算出的校验值，
+/// 校验值与值由同一次写入更新，因此并发修改节点的指针不会使二者暂时不一致。
+/// 校验值不包括删除标记，标记节点时只需修改标记位
+#[cfg(feature = "node-canary")]
+const fn encode(value: usize) -> usize {
+    let low = value & ((1 << VALUE_BITS) - 1);
+    let sum = (low & !DELETE_MARK) ^ CANARY;
+    let sum = (sum ^ (sum >> 16) ^ (sum >> 32)) & 0xffff;
+    (sum << VALUE_BITS) | low
+}
+
+#[cfg(not(feature = "node-canary"))]
+const fn encode(value: usize) -> usize {
+    value
+}
 
 // 此处，使用了指针的最低位作为标记。
 // 为了保证这样带标记的指针能够进行正常的位置无关地址转换，
@@ -71,11 +106,32 @@ where
 /// 其指针字段可以看作一个可能带有标记的、地址无关的、原子的指针。
 /// 其引用计数字段用于避免其它线程正在访问节点时，某个线程释放了该节点。
 /// 注意：应该通过NodePtr访问ListNode，以正确维护引用计数。
+///
+/// 内存序：
+/// 引用计数的作用依赖于“先增加 b 的引用计数，再验证 a 的值未变”与“先修改 a，再读取 b 的引用计数”
+/// 两组操作之间的全序关系，因此增加引用计数、验证时的读取、修改链表的 CAS 以及等待引用计数时的读取
+/// 都需要使用 SeqCst。其余操作只需要 Acquire/Release 或 Relaxed。
 pub(crate) struct ListNode {
-    ptr: AtomicWrappedPtr<MarkedPtr<PIPtr>>,
+    // 存储 MarkedPtr<PIPtr> 的值，即可能带有标记的位置无关地址
+    ptr: AtomicPtr<()>,
+    // 启用 `node-canary` 时，低 32 位为引用计数，高 32 位为由节点的位置无关地址计算出的校验值
+    // ptr 的校验值见 `encode`
     rc: AtomicUsize,
 }
 
+#[cfg(all(feature = "node-canary", not(target_pointer_width = "64")))]
+compile_error!("feature `node-canary` requires a 64-bit target");
+
+/// 与节点位置无关地址的低 32 位异或得到校验值
+#[cfg(feature = "node-canary")]
+const CANARY: usize = 0x5049_4c46;
+#[cfg(feature = "node-canary")]
+const CANARY_SHIFT: u32 = 32;
+#[cfg(feature = "node-canary")]
+const RC_MASK: usize = (1 << CANARY_SHIFT) - 1;
+#[cfg(not(feature = "node-canary"))]
+const RC_MASK: usize = usize::MAX;
+
 impl ListNode {
     /// 将指向该节点的指针转换为对该节点的引用
     /// 该函数中不需要地址转换，因为其不涉及将指针存储入节点。
@@ -95,28 +151,13 @@ impl ListNode {
 
     /// 以NodePtr形式，返回节点自身指向的下一个节点的指针
     pub(crate) fn marked_ptr(&self) -> NodePtr {
-        let mut ptr = NodePtr::from_marked_ptr(self.ptr.load().marked_ptr());
+        let mut ptr = NodePtr::from_marked_ptr(self.load().marked_ptr());
         loop {
-            let new_ptr = NodePtr::from_marked_ptr(self.ptr.load().marked_ptr());
+            let new_ptr = NodePtr::from_marked_ptr(self.load_seqcst().marked_ptr());
             // 在NodePtr构造函数中增加引用计数后，验证self的（去掉标记的）值是否改变
             // 若未改变，则说明ptr指向的节点不会在增加引用计数前被释放，因此可以返回ptr
             // 否则，需要重新获取ptr
             if ptr.unmark() == new_ptr.unmark() {
-                if !ptr.is_null() {
-                    assert!(
-                        (ptr.unmark() as usize) >= (NODE_LBOUND.load(Ordering::SeqCst) as usize)
-                    );
-                    assert!(
-                        (ptr.unmark() as usize) < (NODE_UBOUND.load(Ordering::SeqCst) as usize)
-                    );
-                }
-                assert!(
-                    ptr.is_null()
-                        || unsafe { *(ptr.ptr() as *mut usize) } == NULL_PTR
-                        || unsafe { *(ptr.ptr() as *mut usize) } == (NULL_PTR | DELETE_MARK)
-                        || unsafe { *((*(ptr.ptr() as *mut usize) & !DELETE_MARK) as *mut usize) }
-                            != 3
-                );
                 return ptr;
             }
             ptr = new_ptr
@@ -128,53 +169,96 @@ impl ListNode {
     }
 
     pub(crate) fn rc_decrease(&self) {
-        self.rc.fetch_sub(1, Ordering::SeqCst);
+        // Release：本线程对节点的访问先于等待引用计数的线程释放节点
+        let _old = self.rc.fetch_sub(1, Ordering::Release);
         // 溢出检测
-        assert!(self.rc.load(Ordering::SeqCst) & (usize::MAX - (usize::MAX >> 1)) == 0);
+        #[cfg(feature = "debug-checks")]
+        assert!(_old & RC_MASK != 0, "list node reference count underflow");
     }
 
     pub(crate) fn rc(&self) -> usize {
-        self.rc.load(Ordering::SeqCst)
+        self.rc.load(Ordering::SeqCst) & RC_MASK
+    }
+
+    /// 清零引用计数，用于新加入链表的节点，启用 `node-canary` 时同时写入校验值
+    /// 节点由随后修改链表的 CAS 发布，因此可以使用 Relaxed
+    pub(crate) fn rc_reset(&self) {
+        #[cfg(not(feature = "node-canary"))]
+        let value = 0;
+        #[cfg(feature = "node-canary")]
+        let value = self.expected_canary() << CANARY_SHIFT;
+        self.rc.store(value, Ordering::Relaxed);
+    }
+
+    /// 节点位于当前地址时应有的校验值
+    #[cfg(feature = "node-canary")]
+    pub(crate) fn expected_canary(&self) -> usize {
+        let offset = (self as *const Self as usize).wrapping_sub(get_data_base());
+        (CANARY ^ offset) & RC_MASK
+    }
+
+    #[cfg(feature = "node-canary")]
+    pub(crate) fn canary(&self) -> usize {
+        self.rc.load(Ordering::Relaxed) >> CANARY_SHIFT
+    }
+
+    /// 指针字段的完整值，以及按其中的地址计算出的应有的值
+    #[cfg(feature = "node-canary")]
+    pub(crate) fn ptr_checksum(&self) -> (usize, usize) {
+        let raw = self.ptr.load(Ordering::Relaxed);
+        (raw as usize, encode(untag(raw) as usize))
     }
 }
 
 // 暴露内部方法
 impl ListNode {
     pub(crate) fn load_value(&self) -> *mut () {
-        self.ptr.load_value()
+        untag(self.ptr.load(Ordering::Acquire))
     }
 
     pub(crate) fn load_ptr(&self) -> *mut () {
-        self.ptr.load_ptr()
+        self.load().ptr()
     }
 
     pub(crate) fn load(&self) -> MarkedPtr<PIPtr> {
-        self.ptr.load()
+        MarkedPtr::from_value(self.load_value())
+    }
+
+    /// 与 load 相同，但使用 SeqCst，用于引用计数的验证
+    fn load_seqcst(&self) -> MarkedPtr<PIPtr> {
+        MarkedPtr::from_value(untag(self.ptr.load(Ordering::SeqCst)))
+    }
+
+    /// 读取包括版本号在内的完整值，只用于链表头
+    #[cfg(feature = "tagged-head")]
+    pub(crate) fn load_raw(&self) -> *mut () {
+        self.ptr.load(Ordering::SeqCst)
     }
 
     pub(crate) fn from_value(value: *mut ()) -> Self {
         Self {
-            ptr: AtomicWrappedPtr::from_value(value),
+            ptr: AtomicPtr::new(encode(value as usize) as *mut ()),
             rc: AtomicUsize::new(0),
         }
     }
 
     pub(crate) fn from_ptr(ptr: *mut ()) -> Self {
-        Self {
-            ptr: AtomicWrappedPtr::from_ptr(ptr),
-            rc: AtomicUsize::new(0),
-        }
+        Self::from_value(MarkedPtr::<PIPtr>::from_ptr(ptr).value())
     }
 
-    pub(crate) const fn null() -> Self {
-        Self {
-            ptr: AtomicWrappedPtr::null(),
-            rc: AtomicUsize::new(0),
+    const_fn! {
+        pub(crate) fn null() -> Self {
+            Self {
+                ptr: AtomicPtr::new(encode(NULL_PTR) as *mut ()),
+                rc: AtomicUsize::new(0),
+            }
         }
     }
 
+    /// 只用于尚未加入链表的节点，节点由随后修改链表的 CAS 发布，因此可以使用 Relaxed
     pub(crate) fn store(&self, value: *mut ()) {
-        self.ptr.store(value);
+        self.ptr
+            .store(encode(value as usize) as *mut (), Ordering::Relaxed);
     }
 
     pub(crate) fn compare_exchange(
@@ -182,19 +266,39 @@ impl ListNode {
         current: *mut (),
         new: *mut (),
     ) -> Result<*mut (), *mut ()> {
-        self.ptr.compare_exchange(current, new)
+        // 失败时调用者只会重试或重新搜索，不依赖读到的值
+        self.ptr
+            .compare_exchange(
+                encode(current as usize) as *mut (),
+                encode(new as usize) as *mut (),
+                Ordering::SeqCst,
+                Ordering::Relaxed,
+            )
+            .map(untag)
+            .map_err(untag)
+    }
+
+    /// 以包括版本号在内的完整值进行 CAS，只用于链表头
+    #[cfg(feature = "tagged-head")]
+    pub(crate) fn compare_exchange_raw(
+        &self,
+        current: *mut (),
+        new: *mut (),
+    ) -> Result<*mut (), *mut ()> {
+        self.ptr
+            .compare_exchange(current, new, Ordering::SeqCst, Ordering::Relaxed)
     }
 
     pub(crate) fn is_marked(&self) -> bool {
-        self.ptr.load().is_marked()
+        self.load().is_marked()
     }
 
     pub(crate) fn mark(&self) -> *mut () {
-        self.ptr.load().mark()
+        self.load().mark()
     }
 
     pub(crate) fn unmark(&self) -> *mut () {
-        self.ptr.load().unmark()
+        self.load().unmark()
     }
 }
 
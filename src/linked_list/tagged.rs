@@ -0,0 +1,35 @@
+//! 带版本号的链表头
+//!
+//! 链表头的值的高 16 位用作版本号，每次修改链表头时加一。
+//! push 在查找前记录链表头的完整值，并以该值进行 CAS，
+//! 因此在此期间即使链表头经过 pop、push 后又变回原来的节点，CAS 也会失败。
+//!
+//! 低 48 位存储位置无关地址（即偏移量），读取时进行符号扩展，
+//! 因此要求节点与数据段基地址之间的距离小于 2^47。
+//! 其余节点的值不带版本号（启用 `node-canary` 时高 16 位为校验值），读取时的符号扩展对其没有影响。
+
+#[cfg(not(target_pointer_width = "64"))]
+compile_error!("feature `tagged-head` requires a 64-bit target");
+
+pub(crate) use super::node_ptr::untag;
+use super::node_ptr::{ListNode, VALUE_BITS as TAG_SHIFT};
+
+const VALUE_MASK: usize = (1 << TAG_SHIFT) - 1;
+
+/// 以 raw 的下一个版本号标记 value
+fn next_tag(raw: *mut (), value: *mut ()) -> *mut () {
+    let tag = ((raw as usize) >> TAG_SHIFT).wrapping_add(1);
+    ((tag << TAG_SHIFT) | (value as usize & VALUE_MASK)) as *mut ()
+}
+
+/// 若链表头的完整值仍为 snapshot，则将其修改为 new，并增加版本号
+pub(crate) fn cas_snapshot(head: &ListNode, snapshot: *mut (), new: *mut ()) -> bool {
+    head.compare_exchange_raw(snapshot, next_tag(snapshot, new))
+        .is_ok()
+}
+
+/// 若链表头（去掉版本号后）的值为 current，则将其修改为 new，并增加版本号
+pub(crate) fn cas(head: &ListNode, current: *mut (), new: *mut ()) -> bool {
+    let snapshot = head.load_raw();
+    untag(snapshot) == current && cas_snapshot(head, snapshot, new)
+}
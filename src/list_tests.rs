@@ -1,8 +1,6 @@
 use pi_pointer::NULL_PTR;
 
 use crate::linked_list;
-use crate::linked_list::NODE_LBOUND;
-use crate::linked_list::NODE_UBOUND;
 use crate::LinkedList;
 use core::mem::size_of;
 use core::ptr::null_mut;
@@ -11,9 +9,11 @@ use core::sync::atomic::Ordering;
 use core::usize;
 
 use crate::get_data_base;
+use crate::heap_tests::lock_heap_base;
 
 #[test]
 fn test_linked_list_func() {
+    let _base = lock_heap_base(0);
     let mut value1: [usize; 2] = [0; 2];
     let mut value2: [usize; 2] = [0; 2];
     let mut value3: [usize; 2] = [0; 2];
@@ -29,18 +29,18 @@ fn test_linked_list_func() {
     // Test links
     // 访问链表内的内容，因此需要偏移
     assert_eq!(
-        value4[0],
+        node_value(value4[0]),
         (&value3 as *const usize as usize) - get_data_base()
     );
     assert_eq!(
-        value3[0],
+        node_value(value3[0]),
         (&value2 as *const usize as usize) - get_data_base()
     );
     assert_eq!(
-        value2[0],
+        node_value(value2[0]),
         (&value1 as *const usize as usize) - get_data_base()
     );
-    assert_eq!(value1[0], NULL_PTR as usize);
+    assert_eq!(node_value(value1[0]), NULL_PTR as usize);
 
     // Test delete
     assert_eq!(list.delete(&mut value2 as *mut [usize] as *mut ()), true);
@@ -70,6 +70,7 @@ fn test_linked_list_func() {
 #[test]
 #[allow(unused_assignments)]
 fn test_delete() {
+    let _base = lock_heap_base(0);
     use linked_list::DELETE_MARK;
 
     let mut value1: [usize; 2] = [0; 2];
@@ -151,10 +152,21 @@ fn test_delete() {
     assert_eq!(list.pop(), None);
 }
 
+/// 链表头中存储的值，启用 `tagged-head` 时去掉版本号
+fn head_value(list: &LinkedList) -> usize {
+    node_value(unsafe { *(list as *const LinkedList as *const () as *const usize) })
+}
+
+/// 去掉节点的值中的版本号或校验值
+fn node_value(raw: usize) -> usize {
+    linked_list::untag(raw as *mut ()) as usize
+}
+
 #[test]
 #[allow(unused_assignments)]
 /// 测试search函数是否能正常地删除搜索元素旁边的标记元素
 fn test_search() {
+    let _base = lock_heap_base(0);
     use linked_list::DELETE_MARK;
 
     let mut value1: [usize; 2] = [0; 2];
@@ -173,21 +185,18 @@ fn test_search() {
     drop(right_node);
     // list: head-->value1-->value2-->value3-->NULL
     assert_eq!(
-        unsafe { *(&list as *const LinkedList as *const () as *const usize) },
+        head_value(&list),
         &value1 as *const [usize] as *const () as usize
     );
     assert_eq!(
-        unsafe { *(&value1 as *const [usize] as *const () as *const usize) },
+        node_value(value1[0]),
         &value2 as *const [usize] as *const () as usize
     );
     assert_eq!(
-        unsafe { *(&value2 as *const [usize] as *const () as *const usize) },
+        node_value(value2[0]),
         &value3 as *const [usize] as *const () as usize
     );
-    assert_eq!(
-        unsafe { *(&value3 as *const [usize] as *const () as *const usize) },
-        NULL_PTR
-    );
+    assert_eq!(node_value(value3[0]), NULL_PTR);
     while let Some(_) = list.pop() {}
 
     // 标记情况：无、无、有
@@ -202,21 +211,18 @@ fn test_search() {
     drop(right_node);
     // list: head-->value1-->value2-->value3(marked)-->NULL
     assert_eq!(
-        unsafe { *(&list as *const LinkedList as *const () as *const usize) },
+        head_value(&list),
         &value1 as *const [usize] as *const () as usize
     );
     assert_eq!(
-        unsafe { *(&value1 as *const [usize] as *const () as *const usize) },
+        node_value(value1[0]),
         &value2 as *const [usize] as *const () as usize
     );
     assert_eq!(
-        unsafe { *(&value2 as *const [usize] as *const () as *const usize) },
+        node_value(value2[0]),
         &value3 as *const [usize] as *const () as usize
     );
-    assert_eq!(
-        unsafe { *(&value3 as *const [usize] as *const () as *const usize) },
-        NULL_PTR | DELETE_MARK
-    );
+    assert_eq!(node_value(value3[0]), NULL_PTR | DELETE_MARK);
     while let Some(_) = list.pop() {}
 
     // 标记情况：无、有、无
@@ -231,17 +237,14 @@ fn test_search() {
     drop(right_node);
     // list: head-->value1-->value3-->NULL
     assert_eq!(
-        unsafe { *(&list as *const LinkedList as *const () as *const usize) },
+        head_value(&list),
         &value1 as *const [usize] as *const () as usize
     );
     assert_eq!(
-        unsafe { *(&value1 as *const [usize] as *const () as *const usize) },
+        node_value(value1[0]),
         &value3 as *const [usize] as *const () as usize
     );
-    assert_eq!(
-        unsafe { *(&value3 as *const [usize] as *const () as *const usize) },
-        NULL_PTR
-    );
+    assert_eq!(node_value(value3[0]), NULL_PTR);
     while let Some(_) = list.pop() {}
 
     // 标记情况：无、有、有
@@ -257,13 +260,10 @@ fn test_search() {
     drop(right_node);
     // list: head-->value1-->NULL
     assert_eq!(
-        unsafe { *(&list as *const LinkedList as *const () as *const usize) },
+        head_value(&list),
         &value1 as *const [usize] as *const () as usize
     );
-    assert_eq!(
-        unsafe { *(&value1 as *const [usize] as *const () as *const usize) },
-        NULL_PTR
-    );
+    assert_eq!(node_value(value1[0]), NULL_PTR);
     while let Some(_) = list.pop() {}
 
     // 标记情况：有、无、无
@@ -278,17 +278,14 @@ fn test_search() {
     drop(right_node);
     // list: head-->value2-->value3-->NULL
     assert_eq!(
-        unsafe { *(&list as *const LinkedList as *const () as *const usize) },
+        head_value(&list),
         &value2 as *const [usize] as *const () as usize
     );
     assert_eq!(
-        unsafe { *(&value2 as *const [usize] as *const () as *const usize) },
+        node_value(value2[0]),
         &value3 as *const [usize] as *const () as usize
     );
-    assert_eq!(
-        unsafe { *(&value3 as *const [usize] as *const () as *const usize) },
-        NULL_PTR
-    );
+    assert_eq!(node_value(value3[0]), NULL_PTR);
     while let Some(_) = list.pop() {}
 
     // 标记情况：有、无、有
@@ -304,17 +301,14 @@ fn test_search() {
     drop(right_node);
     // list: head-->value2-->value3(marked)-->NULL
     assert_eq!(
-        unsafe { *(&list as *const LinkedList as *const () as *const usize) },
+        head_value(&list),
         &value2 as *const [usize] as *const () as usize
     );
     assert_eq!(
-        unsafe { *(&value2 as *const [usize] as *const () as *const usize) },
+        node_value(value2[0]),
         &value3 as *const [usize] as *const () as usize
     );
-    assert_eq!(
-        unsafe { *(&value3 as *const [usize] as *const () as *const usize) },
-        NULL_PTR | DELETE_MARK
-    );
+    assert_eq!(node_value(value3[0]), NULL_PTR | DELETE_MARK);
     while let Some(_) = list.pop() {}
 
     // 标记情况：有、有、无
@@ -330,13 +324,10 @@ fn test_search() {
     drop(right_node);
     // list: head-->value3-->NULL
     assert_eq!(
-        unsafe { *(&list as *const LinkedList as *const () as *const usize) },
+        head_value(&list),
         &value3 as *const [usize] as *const () as usize
     );
-    assert_eq!(
-        unsafe { *(&value3 as *const [usize] as *const () as *const usize) },
-        NULL_PTR
-    );
+    assert_eq!(node_value(value3[0]), NULL_PTR);
     while let Some(_) = list.pop() {}
 
     // 标记情况：有、有、有
@@ -352,15 +343,77 @@ fn test_search() {
     drop(left_node);
     drop(right_node);
     // list: head-->NULL
+    assert_eq!(head_value(&list), NULL_PTR);
+    while let Some(_) = list.pop() {}
+}
+
+#[cfg(feature = "debug-checks")]
+#[test]
+#[should_panic(expected = "out of bounds")]
+fn test_node_bounds() {
+    let _base = lock_heap_base(0);
+    let mut value1: [usize; 2] = [0; 2];
+    let mut value2: [usize; 2] = [0; 2];
+    let list = linked_list::LinkedList::new();
+    let start = &mut value1 as *mut [usize] as *mut () as usize;
+    list.set_node_bounds(start, start + size_of::<[usize; 2]>());
+    unsafe {
+        list.push(&mut value1 as *mut [usize] as *mut ());
+        // value2 不在 [value1, value1 + 16) 范围内
+        list.push(&mut value2 as *mut [usize] as *mut ());
+    }
+}
+
+#[cfg(feature = "tagged-head")]
+#[test]
+fn test_tagged_head() {
+    let _base = lock_heap_base(0);
+    let mut value1: [usize; 2] = [0; 2];
+    let mut value2: [usize; 2] = [0; 2];
+    let ptr1 = &mut value1 as *mut [usize] as *mut ();
+    let ptr2 = &mut value2 as *mut [usize] as *mut ();
+    let list = linked_list::LinkedList::new();
+    unsafe {
+        list.push(ptr2);
+        list.push(ptr1);
+    }
+    // list: head-->value1-->value2-->NULL
+    let raw = list.head_raw();
+
+    // 经过 pop、push 后链表头指向相同的节点，但版本号不同
+    assert_eq!(list.pop(), Some(ptr1));
+    unsafe { list.push(ptr1) };
+    assert_ne!(list.head_raw(), raw);
     assert_eq!(
-        unsafe { *(&list as *const LinkedList as *const () as *const usize) },
-        NULL_PTR
+        list.head_raw() as usize & ((1 << 48) - 1),
+        raw as usize & ((1 << 48) - 1)
     );
-    while let Some(_) = list.pop() {}
+
+    // 确定性地重现 ABA：push 记录链表头后，其它操作 pop、push 使链表头变回原来的节点，
+    // 此时去掉版本号的值相同，但以记录的完整值进行的 CAS 必须失败
+    let offset1 = (ptr1 as usize).wrapping_sub(get_data_base()) as *mut ();
+    let snapshot = list.head_raw();
+    assert_eq!(list.pop(), Some(ptr1));
+    unsafe { list.push(ptr1) };
+    assert_eq!(
+        node_value(list.head_raw() as usize),
+        node_value(snapshot as usize)
+    );
+    assert!(!list.cas_head_snapshot(snapshot, offset1));
+    // 以最新的完整值进行的 CAS 成功，并再次改变版本号
+    let current = list.head_raw();
+    assert!(list.cas_head_snapshot(current, offset1));
+    assert_ne!(list.head_raw(), current);
+
+    // 带版本号的链表头不影响链表的其它操作
+    assert!(list.delete(ptr2));
+    assert_eq!(list.pop(), Some(ptr1));
+    assert!(list.is_empty());
 }
 
 #[test]
 fn test_linked_list_concurrent() {
+    let _base = lock_heap_base(0);
     use std::sync::Arc;
     use std::thread;
 
@@ -387,9 +440,11 @@ fn test_linked_list_concurrent() {
             Arc::new([const { AtomicUsize::new(0) }; NUM_PRODUCERS * NUM_DATA_PER_THREAD]);
         let list = Arc::new(linked_list::LinkedList::new());
 
-        let node_addr_range = values.as_ptr_range();
-        NODE_LBOUND.store(node_addr_range.start as *mut (), Ordering::SeqCst);
-        NODE_UBOUND.store(node_addr_range.end as *mut (), Ordering::SeqCst);
+        #[cfg(feature = "debug-checks")]
+        {
+            let node_addr_range = values.as_ptr_range();
+            list.set_node_bounds(node_addr_range.start as usize, node_addr_range.end as usize);
+        }
 
         for i in 0..NUM_PRODUCERS {
             let l = list.clone();
@@ -0,0 +1,266 @@
+//! 使用 loom 对链表和堆的并发操作进行模型检查
+//! loom 会枚举线程间所有可能的交错执行（在抢占次数限制内），因此每个测试只使用很少的线程和节点。
+
+use crate::linked_list::ListNode;
+use crate::{LinkedList, LockFreeHeap};
+use core::alloc::Layout;
+use core::mem::size_of;
+use loom::sync::Arc;
+use loom::thread;
+use pi_pointer::GetDataBase;
+
+struct GetDataBaseImpl;
+
+#[crate_interface::impl_interface]
+impl GetDataBase for GetDataBaseImpl {
+    fn get_data_base() -> usize {
+        0
+    }
+}
+
+/// 一个最小块。loom 的原子类型只能在模型内构造，因此每个最小块的开头都预先构造了一个链表节点，
+/// 使得切分与合并产生的任何块的起始地址处都是有效的节点。
+#[repr(C, align(16))]
+struct Slot(ListNode);
+
+/// 按自身大小对齐的 4 个最小块，加入堆后是一个完整的块
+#[repr(C, align(64))]
+struct Region([Slot; 4]);
+
+fn slots(n: usize) -> Arc<Vec<Slot>> {
+    assert_eq!(size_of::<Slot>(), crate::imp::MIN_BLOCK_SIZE);
+    Arc::new((0..n).map(|_| Slot(ListNode::null())).collect())
+}
+
+fn addr(slots: &[Slot], i: usize) -> *mut () {
+    &slots[i] as *const Slot as *mut ()
+}
+
+fn model(f: impl Fn() + Sync + Send + 'static) {
+    model_bounded(3, f)
+}
+
+/// 状态空间较大的模型使用较小的抢占次数上限，环境变量 LOOM_MAX_PREEMPTIONS 优先
+fn model_bounded(bound: usize, f: impl Fn() + Sync + Send + 'static) {
+    let mut builder = loom::model::Builder::new();
+    if builder.preemption_bound.is_none() {
+        builder.preemption_bound = Some(bound);
+    }
+    builder.check(f);
+}
+
+#[test]
+fn loom_push_push() {
+    model(|| {
+        let nodes = slots(2);
+        let list = Arc::new(LinkedList::new());
+        let handles: Vec<_> = (0..2)
+            .map(|i| {
+                let (nodes, list) = (nodes.clone(), list.clone());
+                thread::spawn(move || unsafe { list.push(addr(&nodes, i)) })
+            })
+            .collect();
+        for h in handles {
+            h.join().unwrap();
+        }
+        let mut popped = [list.pop().unwrap(), list.pop().unwrap()];
+        popped.sort();
+        assert_eq!(popped, [addr(&nodes, 0), addr(&nodes, 1)]);
+        assert_eq!(list.pop(), None);
+    });
+}
+
+#[test]
+fn loom_push_pop() {
+    model(|| {
+        let nodes = slots(2);
+        let list = Arc::new(LinkedList::new());
+        unsafe { list.push(addr(&nodes, 0)) };
+        let pusher = {
+            let (nodes, list) = (nodes.clone(), list.clone());
+            thread::spawn(move || unsafe { list.push(addr(&nodes, 1)) })
+        };
+        let popper = {
+            let list = list.clone();
+            thread::spawn(move || list.pop())
+        };
+        pusher.join().unwrap();
+        let first = popper.join().unwrap().unwrap();
+        let second = list.pop().unwrap();
+        assert_ne!(first, second);
+        assert_eq!(list.pop(), None);
+    });
+}
+
+#[test]
+fn loom_pop_pop() {
+    model(|| {
+        let nodes = slots(2);
+        let list = Arc::new(LinkedList::new());
+        unsafe {
+            list.push(addr(&nodes, 0));
+            list.push(addr(&nodes, 1));
+        }
+        let handles: Vec<_> = (0..2)
+            .map(|_| {
+                let list = list.clone();
+                thread::spawn(move || list.pop())
+            })
+            .collect();
+        let mut popped: Vec<_> = handles
+            .into_iter()
+            .map(|h| h.join().unwrap().unwrap())
+            .collect();
+        popped.sort();
+        assert_eq!(popped, [addr(&nodes, 0), addr(&nodes, 1)]);
+        assert!(list.is_empty());
+    });
+}
+
+#[test]
+fn loom_pop_delete() {
+    model(|| {
+        let nodes = slots(2);
+        let list = Arc::new(LinkedList::new());
+        // list: head-->1-->0
+        unsafe {
+            list.push(addr(&nodes, 0));
+            list.push(addr(&nodes, 1));
+        }
+        let popper = {
+            let list = list.clone();
+            thread::spawn(move || list.pop())
+        };
+        let deleter = {
+            let (nodes, list) = (nodes.clone(), list.clone());
+            thread::spawn(move || list.delete(addr(&nodes, 0)))
+        };
+        assert_eq!(popper.join().unwrap(), Some(addr(&nodes, 1)));
+        assert!(deleter.join().unwrap());
+        assert!(list.is_empty());
+    });
+}
+
+#[test]
+fn loom_alloc_dealloc() {
+    model_bounded(2, || {
+        let region = Arc::new(Region(core::array::from_fn(|_| Slot(ListNode::null()))));
+        let heap = Arc::new(LockFreeHeap::<8>::new());
+        let start = &region.0 as *const [Slot; 4] as usize;
+        unsafe { heap.add_to_heap(start, start + size_of::<Region>()) };
+        let layout = Layout::from_size_align(1, 1).unwrap();
+        let block = heap.alloc_(layout).unwrap();
+
+        let freer = {
+            let heap = heap.clone();
+            let block = block.as_ptr() as usize;
+            thread::spawn(move || {
+                heap.dealloc_(core::ptr::NonNull::new(block as *mut u8).unwrap(), layout)
+            })
+        };
+        let allocator = {
+            let heap = heap.clone();
+            thread::spawn(move || heap.alloc_(layout).map(|ptr| ptr.as_ptr() as usize))
+        };
+        freer.join().unwrap();
+        // 合并过程中，被合并的块暂时由释放线程持有，此时并发的分配可能失败
+        // （见 doc/堆分配器阅读笔记及修改方案.md），但不能分配到重复的块，也不能丢失块
+        if let Ok(other) = allocator.join().unwrap() {
+            assert_eq!(heap.stats_alloc_actual(), size_of::<Slot>());
+            heap.dealloc_(core::ptr::NonNull::new(other as *mut u8).unwrap(), layout);
+        }
+        assert_eq!(heap.stats_alloc_actual(), 0);
+
+        // 所有块都已合并，整个区域可以作为一个块分配出去
+        let whole = Layout::from_size_align(size_of::<Region>(), 1).unwrap();
+        assert_eq!(heap.alloc_(whole).unwrap().as_ptr() as usize, start);
+    });
+}
+
+#[test]
+fn loom_pop_delete_same() {
+    model(|| {
+        let nodes = slots(2);
+        let list = Arc::new(LinkedList::new());
+        // list: head-->1-->0，两个线程争夺节点 1
+        unsafe {
+            list.push(addr(&nodes, 0));
+            list.push(addr(&nodes, 1));
+        }
+        let popper = {
+            let list = list.clone();
+            thread::spawn(move || list.pop())
+        };
+        let deleter = {
+            let (nodes, list) = (nodes.clone(), list.clone());
+            thread::spawn(move || list.delete(addr(&nodes, 1)))
+        };
+        let popped = popper.join().unwrap().unwrap();
+        // 节点 1 只能被其中一个线程取得
+        if deleter.join().unwrap() {
+            assert_eq!(popped, addr(&nodes, 0));
+        } else {
+            assert_eq!(popped, addr(&nodes, 1));
+            assert_eq!(list.pop(), Some(addr(&nodes, 0)));
+        }
+        assert!(list.is_empty());
+    });
+}
+
+#[test]
+fn loom_delete_delete_same() {
+    model(|| {
+        let nodes = slots(2);
+        let list = Arc::new(LinkedList::new());
+        unsafe {
+            list.push(addr(&nodes, 0));
+            list.push(addr(&nodes, 1));
+        }
+        let handles: Vec<_> = (0..2)
+            .map(|_| {
+                let (nodes, list) = (nodes.clone(), list.clone());
+                thread::spawn(move || list.delete(addr(&nodes, 0)))
+            })
+            .collect();
+        let deleted: Vec<_> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+        assert_eq!(deleted.iter().filter(|d| **d).count(), 1);
+        assert_eq!(list.pop(), Some(addr(&nodes, 1)));
+        assert!(list.is_empty());
+    });
+}
+
+/// 节点被取出后立即被复用（加入另一个链表，指针被改为未标记的值），
+/// 对应 doc/无锁列表当前bug描述.md 中描述的情形。
+/// 另一个线程可能在节点被取出前读到了指向它的指针，并在节点被复用后才增加、减少其引用计数，
+/// 从而破坏复用后节点的引用计数，使之后的 pop 永远等待，loom 因此报告超出分支数上限
+#[test]
+#[ignore = "known bug: doc/无锁列表当前bug描述.md"]
+fn loom_pop_reuse() {
+    model(|| {
+        let nodes = slots(2);
+        let list = Arc::new(LinkedList::new());
+        let other = Arc::new(LinkedList::new());
+        unsafe {
+            list.push(addr(&nodes, 0));
+            list.push(addr(&nodes, 1));
+        }
+        let reuser = {
+            let (list, other) = (list.clone(), other.clone());
+            thread::spawn(move || {
+                let node = list.pop().unwrap();
+                unsafe { other.push(node) };
+                node
+            })
+        };
+        let popper = {
+            let list = list.clone();
+            thread::spawn(move || list.pop())
+        };
+        let reused = reuser.join().unwrap();
+        let popped = popper.join().unwrap().unwrap();
+        assert_ne!(reused, popped);
+        assert!(list.is_empty());
+        assert_eq!(other.pop(), Some(reused));
+        assert!(other.is_empty());
+    });
+}
@@ -0,0 +1,263 @@
+//! 与参考 buddy 分配器的差分测试
+//!
+//! 用 proptest 生成随机的 `add_to_heap`、`alloc_`、`dealloc_` 序列，
+//! 同时作用于 `LockFreeHeap` 和单线程的参考模型 [`Model`]，并比较二者的结果。
+//! 模型独立实现块大小的计算，不使用 `imp` 中的函数。
+//! 空闲链表的顺序属于实现细节，因此模型只规定分配应从哪一级取块，具体取哪个块由堆决定，
+//! 比较空闲块时也不考虑顺序。
+
+use core::alloc::Layout;
+use core::mem::size_of;
+use std::collections::BTreeSet;
+
+use proptest::prelude::*;
+
+use crate::heap_tests::lock_heap_base;
+use crate::{HeapDump, LockFreeHeap};
+
+const ORDER: usize = 12;
+/// 每个区域所在槽位的大小，每个槽位最多加入一次，因此区域之间不会重叠
+const SLOT_SIZE: usize = 16 * 1024;
+const SLOTS: usize = 4;
+/// 最小的块需要放下空闲链表的节点，即两个字
+const MIN_BLOCK: usize = 2 * size_of::<usize>();
+#[cfg(feature = "red-zones")]
+const RED_ZONE: usize = crate::poison::RED_ZONE_SIZE;
+#[cfg(not(feature = "red-zones"))]
+const RED_ZONE: usize = 0;
+
+/// 满足 layout 的块大小，对齐要求不影响块的大小
+fn block_size(layout: Layout) -> usize {
+    (layout.size() + RED_ZONE)
+        .next_power_of_two()
+        .max(MIN_BLOCK)
+}
+
+/// 不超过 num 的最大的 2 的幂
+fn prev_power_of_two(num: usize) -> usize {
+    1 << num.ilog2()
+}
+
+/// 单线程的参考 buddy 分配器，每一级的空闲块是一个无序集合
+struct Model {
+    free_list: Vec<BTreeSet<usize>>,
+    user: usize,
+    allocated: usize,
+    total: usize,
+}
+
+impl Model {
+    fn new() -> Self {
+        Self {
+            free_list: vec![BTreeSet::new(); ORDER],
+            user: 0,
+            allocated: 0,
+            total: 0,
+        }
+    }
+
+    fn add_to_heap(&mut self, start: usize, end: usize) {
+        let mut current = start.next_multiple_of(MIN_BLOCK);
+        let end = end / MIN_BLOCK * MIN_BLOCK;
+        while current + MIN_BLOCK <= end {
+            let size = (1 << current.trailing_zeros())
+                .min(prev_power_of_two(end - current))
+                .min(1 << (ORDER - 1));
+            self.free_list[size.trailing_zeros() as usize].insert(current);
+            self.total += size;
+            current += size;
+        }
+    }
+
+    /// 分配 layout 时应从哪一级取块，无法分配时返回 None。
+    /// 优先取至少与块大小和对齐要求一样大的最小的块；没有时，从小到大查找起始地址满足对齐要求的较小的块
+    fn alloc_order(&self, layout: Layout) -> Option<usize> {
+        let class = block_size(layout).trailing_zeros() as usize;
+        let align_class = layout.align().trailing_zeros() as usize;
+        (class.max(align_class)..ORDER)
+            .find(|&i| !self.free_list[i].is_empty())
+            .or_else(|| {
+                (class..align_class.min(ORDER)).find(|&i| {
+                    self.free_list[i]
+                        .iter()
+                        .any(|&block| block.is_multiple_of(layout.align()))
+                })
+            })
+    }
+
+    /// 从第 order 级取出 block 分配给 layout，多余的部分切分后放回空闲链表
+    fn take(&mut self, block: usize, order: usize, layout: Layout) -> Result<(), TestCaseError> {
+        prop_assert!(
+            self.free_list[order].remove(&block),
+            "block {:#x} is not free in order {}",
+            block,
+            order
+        );
+        let size = block_size(layout);
+        for j in size.trailing_zeros() as usize..order {
+            self.free_list[j].insert(block + (1 << j));
+        }
+        self.user += layout.size();
+        self.allocated += size;
+        Ok(())
+    }
+
+    fn dealloc(&mut self, addr: usize, layout: Layout) {
+        let size = block_size(layout);
+        let mut current = addr;
+        let mut class = size.trailing_zeros() as usize;
+        while class < ORDER - 1 && self.free_list[class].remove(&(current ^ (1 << class))) {
+            current &= !(1 << class);
+            class += 1;
+        }
+        self.free_list[class].insert(current);
+        self.user -= layout.size();
+        self.allocated -= size;
+    }
+}
+
+#[derive(Clone, Debug)]
+enum Op {
+    /// 将槽位 slot 中的 [start, start + len) 加入堆
+    Add {
+        slot: usize,
+        start: usize,
+        len: usize,
+    },
+    Alloc {
+        size: usize,
+        align: usize,
+    },
+    /// 释放第 index % 已分配块数 个块
+    Dealloc {
+        index: usize,
+    },
+}
+
+fn op() -> impl Strategy<Value = Op> {
+    prop_oneof![
+        1 => (0..SLOTS, 0..256usize, 64..SLOT_SIZE - 256)
+            .prop_map(|(slot, start, len)| Op::Add { slot, start, len }),
+        4 => (1..3000usize, 0..13u32)
+            .prop_map(|(size, align)| Op::Alloc { size, align: 1 << align }),
+        3 => any::<usize>().prop_map(|index| Op::Dealloc { index }),
+    ]
+}
+
+/// 比较堆与模型的统计信息和空闲链表
+fn check_state(
+    heap: &LockFreeHeap<ORDER>,
+    model: &Model,
+    base: usize,
+) -> Result<(), TestCaseError> {
+    prop_assert_eq!(heap.stats_alloc_user(), model.user);
+    prop_assert_eq!(heap.stats_alloc_actual(), model.allocated);
+    prop_assert_eq!(heap.stats_total_bytes(), model.total);
+
+    let mut buf = vec![0; 64 * 1024];
+    let len = heap.dump(&mut buf).unwrap();
+    let dump = HeapDump::parse(&buf[..len]).unwrap();
+    for (order, list) in model.free_list.iter().enumerate() {
+        let blocks: Vec<usize> = dump
+            .free_blocks(order)
+            .map(|offset| offset + base)
+            .collect();
+        // 同一个块在链表中出现两次时，集合会掩盖这一点，因此还要比较块数
+        prop_assert_eq!(blocks.len(), list.len(), "free list {} length", order);
+        let blocks: BTreeSet<usize> = blocks.into_iter().collect();
+        prop_assert_eq!(&blocks, list, "free list {}", order);
+    }
+    Ok(())
+}
+
+fn run(ops: &[Op]) -> Result<(), TestCaseError> {
+    let backing = Layout::from_size_align(SLOT_SIZE * SLOTS, 4096).unwrap();
+    let base = unsafe { std::alloc::alloc(backing) } as usize;
+    let _base = lock_heap_base(base);
+    let heap = LockFreeHeap::<ORDER>::new();
+    let mut model = Model::new();
+
+    let result = (|| {
+        let mut regions: Vec<(usize, usize)> = Vec::new();
+        let mut used = [false; SLOTS];
+        // 已分配的块及其 layout
+        let mut live: Vec<(usize, Layout)> = Vec::new();
+
+        for op in ops {
+            match *op {
+                Op::Add { slot, start, len } => {
+                    if used[slot] {
+                        continue;
+                    }
+                    used[slot] = true;
+                    let start = base + slot * SLOT_SIZE + start;
+                    unsafe { heap.add_to_heap(start, start + len) };
+                    model.add_to_heap(start, start + len);
+                    regions.push((start, start + len));
+                }
+                Op::Alloc { size, align } => {
+                    let layout = Layout::from_size_align(size, align).unwrap();
+                    let addr = heap.alloc_(layout).ok().map(|addr| addr.as_ptr() as usize);
+                    let order = model.alloc_order(layout);
+                    prop_assert_eq!(addr.is_some(), order.is_some(), "alloc {:?}", layout);
+                    let (Some(addr), Some(order)) = (addr, order) else {
+                        continue;
+                    };
+                    model.take(addr, order, layout)?;
+                    // 与模型无关的性质：对齐、位于某个区域内、与其它已分配的块不重叠
+                    let size = block_size(layout);
+                    prop_assert_eq!(addr % align, 0);
+                    prop_assert_eq!(addr % size, 0);
+                    prop_assert!(regions
+                        .iter()
+                        .any(|&(start, end)| start <= addr && addr + size <= end));
+                    for &(other, other_layout) in &live {
+                        let other_size = block_size(other_layout);
+                        prop_assert!(addr + size <= other || other + other_size <= addr);
+                    }
+                    live.push((addr, layout));
+                }
+                Op::Dealloc { index } => {
+                    if live.is_empty() {
+                        continue;
+                    }
+                    let (addr, layout) = live.swap_remove(index % live.len());
+                    heap.dealloc_(core::ptr::NonNull::new(addr as *mut u8).unwrap(), layout);
+                    model.dealloc(addr, layout);
+                }
+            }
+            check_state(&heap, &model, base)?;
+        }
+
+        // 全部释放后，空闲块应当合并回加入堆时的样子
+        for (addr, layout) in live.drain(..) {
+            heap.dealloc_(core::ptr::NonNull::new(addr as *mut u8).unwrap(), layout);
+            model.dealloc(addr, layout);
+        }
+        check_state(&heap, &model, base)?;
+        let mut initial = Model::new();
+        for &(start, end) in &regions {
+            initial.add_to_heap(start, end);
+        }
+        for order in 0..ORDER {
+            prop_assert_eq!(
+                &model.free_list[order],
+                &initial.free_list[order],
+                "coalesced free list {}",
+                order
+            );
+        }
+        prop_assert_eq!(heap.stats_alloc_actual(), 0);
+        Ok(())
+    })();
+
+    unsafe { std::alloc::dealloc(base as *mut u8, backing) };
+    result
+}
+
+proptest! {
+    #[test]
+    fn model_random_ops(ops in prop::collection::vec(op(), 1..200)) {
+        run(&ops)?;
+    }
+}
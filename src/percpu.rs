@@ -0,0 +1,18 @@
+//! 统计计数器的 CPU 提示
+//!
+//! 启用 `percpu-stats` 后，需要像 `GetDataBase` 一样，
+//! 使用 `crate_interface::impl_interface` 实现 [`CpuHint`]。
+//! 统计计数器按 [`CpuHint::current_cpu`] 的返回值选择分片，使同一个 CPU 上的修改总是落在同一个缓存行上；
+//! 未启用时按块地址选择分片。
+
+/// 当前 CPU 的编号，由使用者实现
+#[crate_interface::def_interface]
+pub trait CpuHint {
+    /// 返回当前 CPU 的编号。只作为选择分片的提示，返回值不必连续，也不要求调用期间不发生迁移。
+    /// 该函数在分配和释放的过程中调用，不应在其中使用同一个堆分配内存
+    fn current_cpu() -> usize;
+}
+
+pub(crate) fn current_cpu() -> usize {
+    crate_interface::call_interface!(CpuHint::current_cpu)
+}
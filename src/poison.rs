@@ -0,0 +1,73 @@
+//! 内存毒化与红区
+//!
+//! 空闲块中链表节点之后的部分填充为 [`FREE_PATTERN`]，分配时检查该部分是否被修改，
+//! 从而发现释放后写入（use-after-free）。被破坏的空闲块会直接破坏无锁链表，因此应尽早发现。
+//!
+//! 已分配块中 `layout.size()` 之后的剩余部分作为红区，填充为 [`RED_ZONE_PATTERN`]，
+//! 释放时检查，从而发现越界写入。块的大小是 2 的幂，剩余部分可能为空；
+//! 启用 `red-zones` 时，每个块在 `layout.size()` 之后至少保留 [`RED_ZONE_SIZE`] 字节。
+
+use crate::error::HeapError;
+use crate::linked_list::NODE_SIZE;
+
+/// 空闲块的填充值
+pub(crate) const FREE_PATTERN: u8 = 0xdd;
+/// 红区的填充值
+pub(crate) const RED_ZONE_PATTERN: u8 = 0xfd;
+/// 红区的最小大小
+#[cfg(feature = "red-zones")]
+pub(crate) const RED_ZONE_SIZE: usize = 16;
+
+/// SAFETY: [addr, addr + len) 需要是有效的内存
+unsafe fn fill(addr: usize, len: usize, pattern: u8) {
+    core::ptr::write_bytes(addr as *mut u8, pattern, len);
+}
+
+/// 返回 [addr, addr + len) 中第一个不等于 pattern 的字节的偏移量
+/// SAFETY: [addr, addr + len) 需要是有效的内存
+unsafe fn check(addr: usize, len: usize, pattern: u8) -> Option<usize> {
+    core::slice::from_raw_parts(addr as *const u8, len)
+        .iter()
+        .position(|&byte| byte != pattern)
+}
+
+/// 毒化即将加入空闲链表的块 [addr, addr + size)，开头的链表节点由链表写入
+/// SAFETY: 块需要由调用者独占
+pub(crate) unsafe fn poison_free(addr: usize, size: usize) {
+    fill(addr + NODE_SIZE, size - NODE_SIZE, FREE_PATTERN);
+}
+
+/// 检查刚分配的块 [addr, addr + size) 是否在空闲期间被写入，然后设置红区。
+/// 发现写入时仍然设置红区，块可以正常使用
+/// SAFETY: 块需要由调用者独占
+pub(crate) unsafe fn check_alloc(
+    addr: usize,
+    size: usize,
+    user_size: usize,
+) -> Result<(), HeapError> {
+    let result = match check(addr + NODE_SIZE, size - NODE_SIZE, FREE_PATTERN) {
+        Some(offset) => Err(HeapError::UseAfterFree {
+            addr,
+            offset: NODE_SIZE + offset,
+        }),
+        None => Ok(()),
+    };
+    fill(addr + user_size, size - user_size, RED_ZONE_PATTERN);
+    result
+}
+
+/// 检查即将释放的块 [addr, addr + size) 的红区
+/// SAFETY: 块需要由调用者独占
+pub(crate) unsafe fn check_free(
+    addr: usize,
+    size: usize,
+    user_size: usize,
+) -> Result<(), HeapError> {
+    match check(addr + user_size, size - user_size, RED_ZONE_PATTERN) {
+        Some(offset) => Err(HeapError::RedZoneOverflow {
+            addr,
+            offset: user_size + offset,
+        }),
+        None => Ok(()),
+    }
+}
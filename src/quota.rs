@@ -0,0 +1,125 @@
+//! 多个租户共享一个堆时的配额
+//!
+//! [`QuotaHeap`] 引用一个 `LockFreeHeap`，为 `TENANTS` 个租户分别记录已分配的字节数和上限。
+//! 分配先在租户的计数器上预留块的大小，超过上限时拒绝；预留成功后才由堆分配，堆内存不足时撤销预留。
+//! 计费按块的实际大小（`stats_alloc_actual` 的口径），租户 id 作为 tag 传给堆，
+//! 因此 `AllocHook` 和 `leaks` 能够看到块所属的租户。
+
+use core::alloc::Layout;
+use core::ptr::NonNull;
+
+use crate::cache_padded::CachePadded;
+use crate::imp::block_size;
+use crate::sync::{AtomicUsize, Ordering};
+use crate::LockFreeHeap;
+
+/// 租户分配失败的原因
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum QuotaError {
+    /// 分配后租户的用量将超过上限
+    QuotaExceeded,
+    /// 堆中没有满足 layout 的空闲块
+    OutOfMemory,
+}
+
+/// 每个租户的计数器独占一个缓存行，避免不同租户的分配之间伪共享
+struct Tenant {
+    used: AtomicUsize,
+    limit: AtomicUsize,
+}
+
+impl Tenant {
+    const_fn! {
+        fn new() -> Self {
+            Self {
+                used: AtomicUsize::new(0),
+                limit: AtomicUsize::new(usize::MAX),
+            }
+        }
+    }
+}
+
+/// 带有租户配额的堆，租户 id 为 `0..TENANTS`，传入超出范围的 id 会 panic
+pub struct QuotaHeap<'a, const ORDER: usize, const TENANTS: usize> {
+    heap: &'a LockFreeHeap<ORDER>,
+    tenants: [CachePadded<Tenant>; TENANTS],
+}
+
+impl<'a, const ORDER: usize, const TENANTS: usize> QuotaHeap<'a, ORDER, TENANTS> {
+    const_fn! {
+        /// 所有租户的上限初始为 `usize::MAX`，即不限制
+        pub fn new(heap: &'a LockFreeHeap<ORDER>) -> Self {
+            Self {
+                heap,
+                #[cfg(not(any(loom, shuttle)))]
+                tenants: [const { CachePadded::new(Tenant::new()) }; TENANTS],
+                #[cfg(any(loom, shuttle))]
+                tenants: core::array::from_fn(|_| CachePadded::new(Tenant::new())),
+            }
+        }
+    }
+
+    /// 共享的堆
+    pub fn heap(&self) -> &'a LockFreeHeap<ORDER> {
+        self.heap
+    }
+
+    /// 设置租户的上限。上限可以低于当前用量，此时该租户的分配都会被拒绝，直到用量降下来
+    pub fn set_limit(&self, tenant: usize, limit: usize) {
+        self.tenants[tenant].limit.store(limit, Ordering::Relaxed);
+    }
+
+    pub fn limit(&self, tenant: usize) -> usize {
+        self.tenants[tenant].limit.load(Ordering::Relaxed)
+    }
+
+    /// 租户当前已分配的字节数，包括正在进行的分配预留的部分
+    pub fn usage(&self, tenant: usize) -> usize {
+        self.tenants[tenant].used.load(Ordering::Relaxed)
+    }
+
+    /// 为租户分配满足 layout 的内存
+    pub fn alloc(&self, tenant: usize, layout: Layout) -> Result<NonNull<u8>, QuotaError> {
+        let size = block_size(layout);
+        let counter = &self.tenants[tenant];
+        let mut used = counter.used.load(Ordering::Relaxed);
+        loop {
+            // 并发修改上限时，以预留时读到的上限为准
+            let limit = counter.limit.load(Ordering::Relaxed);
+            match used.checked_add(size) {
+                Some(new) if new <= limit => {}
+                _ => return Err(QuotaError::QuotaExceeded),
+            }
+            match counter.used.compare_exchange(
+                used,
+                used + size,
+                Ordering::Relaxed,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => break,
+                Err(current) => used = current,
+            }
+        }
+        self.heap.alloc_with_tag(layout, tenant).ok_or_else(|| {
+            counter.used.fetch_sub(size, Ordering::Relaxed);
+            QuotaError::OutOfMemory
+        })
+    }
+
+    /// 释放租户通过 `alloc` 分配的内存，tenant 与 layout 必须与分配时相同。
+    /// 堆放弃了本次释放时（例如启用 `alloc-bitmap` 时的重复释放）不改变用量，返回 false
+    pub fn dealloc(&self, tenant: usize, ptr: NonNull<u8>, layout: Layout) -> bool {
+        if !self.heap.dealloc_with_tag(ptr, layout, tenant) {
+            return false;
+        }
+        // tenant 与分配时不同时用量可能小于块的大小，此时用量降为 0 而不是回绕
+        let size = block_size(layout);
+        let _ =
+            self.tenants[tenant]
+                .used
+                .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |used| {
+                    Some(used.saturating_sub(size))
+                });
+        true
+    }
+}
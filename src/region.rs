@@ -0,0 +1,107 @@
+//! 堆内存区域表
+//!
+//! 记录通过 `add_to_heap` 加入堆的每一段内存区域。
+//! 与链表节点相同，区域地址以相对于数据段基地址的偏移量存储，从而保证位置无关。
+
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use crate::get_data_base;
+
+/// 每个堆最多记录的内存区域数量
+pub(crate) const MAX_REGIONS: usize = 16;
+
+/// 一段内存区域的实际地址
+/// [start, data) 为区域的元数据部分（未启用元数据时为空），[data, end) 为可分配部分
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct RegionInfo {
+    pub(crate) start: usize,
+    pub(crate) data: usize,
+    pub(crate) end: usize,
+}
+
+impl RegionInfo {
+    /// 判断地址是否位于区域的可分配部分
+    pub(crate) fn contains(&self, addr: usize) -> bool {
+        self.data <= addr && addr < self.end
+    }
+}
+
+struct Region {
+    start: AtomicUsize,
+    data: AtomicUsize,
+    end: AtomicUsize,
+    // 区域的各个字段写完后才置位，读者只读取置位的区域
+    ready: AtomicBool,
+}
+
+impl Region {
+    const fn new() -> Self {
+        Self {
+            start: AtomicUsize::new(0),
+            data: AtomicUsize::new(0),
+            end: AtomicUsize::new(0),
+            ready: AtomicBool::new(false),
+        }
+    }
+}
+
+pub(crate) struct RegionTable {
+    regions: [Region; MAX_REGIONS],
+    len: AtomicUsize,
+}
+
+impl RegionTable {
+    pub(crate) const fn new() -> Self {
+        Self {
+            regions: [const { Region::new() }; MAX_REGIONS],
+            len: AtomicUsize::new(0),
+        }
+    }
+
+    /// 区域表是否已满，此时 `register` 一定失败
+    #[cfg(feature = "heap-grow")]
+    pub(crate) fn is_full(&self) -> bool {
+        self.len.load(Ordering::Relaxed) >= MAX_REGIONS
+    }
+
+    /// 记录一段区域，参数均为实际地址
+    /// 返回 false 代表区域表已满，该区域没有被记录
+    pub(crate) fn register(&self, start: usize, data: usize, end: usize) -> bool {
+        let index = self.len.fetch_add(1, Ordering::Relaxed);
+        if index >= MAX_REGIONS {
+            self.len.fetch_sub(1, Ordering::Relaxed);
+            return false;
+        }
+        let base = get_data_base();
+        let region = &self.regions[index];
+        region
+            .start
+            .store(start.wrapping_sub(base), Ordering::Relaxed);
+        region
+            .data
+            .store(data.wrapping_sub(base), Ordering::Relaxed);
+        region.end.store(end.wrapping_sub(base), Ordering::Relaxed);
+        // Release：读者看到 ready 后，一定能看到区域的各个字段
+        region.ready.store(true, Ordering::Release);
+        true
+    }
+
+    /// 查找可分配部分包含 addr 的区域
+    pub(crate) fn find(&self, addr: usize) -> Option<RegionInfo> {
+        self.iter().find(|region| region.contains(addr))
+    }
+
+    /// 遍历所有已记录的区域
+    pub(crate) fn iter(&self) -> impl Iterator<Item = RegionInfo> + '_ {
+        let base = get_data_base();
+        let len = self.len.load(Ordering::Relaxed).min(MAX_REGIONS);
+        self.regions[..len]
+            .iter()
+            .filter(|region| region.ready.load(Ordering::Acquire))
+            .map(move |region| RegionInfo {
+                start: region.start.load(Ordering::Relaxed).wrapping_add(base),
+                data: region.data.load(Ordering::Relaxed).wrapping_add(base),
+                end: region.end.load(Ordering::Relaxed).wrapping_add(base),
+            })
+    }
+}
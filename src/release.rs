@@ -0,0 +1,28 @@
+//! 将空闲的大块归还给操作系统
+//!
+//! 启用 `heap-release` 后，需要像 `GetDataBase` 一样，
+//! 使用 `crate_interface::impl_interface` 实现 [`HeapRelease`]。
+//! `LockFreeHeap::release_free` 将足够大的空闲块移出空闲链表，调用 [`HeapRelease::release_memory`]
+//! 释放其物理内存，然后记录在堆中；分配找不到空闲块时，再调用 [`HeapRelease::reacquire_memory`]
+//! 取回其中的一个块使用。被释放的块不计入 `stats_total_bytes`，也不出现在转储中。
+//! 释放块时若其伙伴是被释放的块，同样会先取回伙伴再合并。
+
+/// 释放与取回物理内存的回调函数，由使用者实现
+#[crate_interface::def_interface]
+pub trait HeapRelease {
+    /// 块 [addr, addr + size) 已经移出堆，可以释放其物理内存，例如 `madvise(MADV_DONTNEED)`。
+    /// 函数返回后，堆会在块的开头写入链表节点以记录该块，因此开头的 16 字节必须仍然可以读写，
+    /// 例如只对第一个内存页之后的部分调用 `munmap`
+    fn release_memory(addr: usize, size: usize);
+
+    /// 之前释放的块 [addr, addr + size) 即将重新加入堆，需要恢复其物理内存
+    fn reacquire_memory(addr: usize, size: usize);
+}
+
+pub(crate) fn release(addr: usize, size: usize) {
+    crate_interface::call_interface!(HeapRelease::release_memory, addr, size)
+}
+
+pub(crate) fn reacquire(addr: usize, size: usize) {
+    crate_interface::call_interface!(HeapRelease::reacquire_memory, addr, size)
+}
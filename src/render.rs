@@ -0,0 +1,229 @@
+//! 根据堆的转储绘制碎片分布图
+//!
+//! 调试合并问题时，可以先用 `LockFreeHeap::dump` 得到转储，再用 [`HeapDump::render_ascii`]
+//! 或 [`HeapDump::render_svg`] 查看每个区域中空闲块的分布，而不需要在调试器中手动计算指针。
+
+use core::fmt;
+
+use crate::{DumpRegion, HeapDump};
+
+/// SVG 中左侧标签的宽度
+const SVG_LABEL: usize = 64;
+/// SVG 中每个区域的宽度
+const SVG_WIDTH: usize = 1024;
+/// SVG 中每一行的高度
+const SVG_ROW: usize = 14;
+/// 已分配内存的颜色
+const SVG_ALLOCATED: &str = "#d06060";
+/// 空闲内存的颜色
+const SVG_FREE: &str = "#60b060";
+/// 各级空闲块所在行的底色
+const SVG_EMPTY: &str = "#eeeeee";
+
+fn in_region(region: &DumpRegion, block: usize) -> bool {
+    region.data <= block && block < region.end
+}
+
+/// 空闲块的结束偏移量，被篡改的转储中可能超出 usize
+fn block_end(block: usize, order: usize) -> usize {
+    block.saturating_add(1 << order)
+}
+
+impl HeapDump<'_> {
+    /// 区域中是否有大小为 2^order 的空闲块
+    fn has_blocks(&self, region: &DumpRegion, order: usize) -> bool {
+        self.free_blocks(order)
+            .any(|block| in_region(region, block))
+    }
+
+    /// 区域中 [start, end) 内空闲内存的字节数
+    fn free_bytes(&self, region: &DumpRegion, start: usize, end: usize) -> usize {
+        (0..self.order())
+            .flat_map(|order| self.free_blocks(order).map(move |block| (block, order)))
+            .filter(|&(block, _)| in_region(region, block))
+            .map(|(block, order)| {
+                let block_end = block_end(block, order).min(region.end);
+                block_end.min(end).saturating_sub(block.max(start))
+            })
+            .sum()
+    }
+
+    /// 区域中 [start, end) 内是否有大小为 2^order 的空闲块
+    fn overlaps(&self, region: &DumpRegion, order: usize, start: usize, end: usize) -> bool {
+        self.free_blocks(order)
+            .any(|block| in_region(region, block) && block < end && start < block_end(block, order))
+    }
+
+    /// 以 ASCII 字符绘制每个区域的碎片分布图，每行 width 个字符
+    ///
+    /// 每个区域先输出一行 `free`，每个字符代表区域中的一段内存：
+    /// `.` 全部空闲，`:` 一半以上空闲，`+` 少部分空闲，`#` 全部已分配。
+    /// 之后从高到低每一级有空闲块的链表输出一行，`-` 代表该段内存中有这一级的空闲块
+    pub fn render_ascii(&self, w: &mut impl fmt::Write, width: usize) -> fmt::Result {
+        let width = width.max(1);
+        for region in self.regions() {
+            let Some(len) = region.end.checked_sub(region.data) else {
+                writeln!(w, "region [{:#x}, {:#x}) invalid", region.data, region.end)?;
+                continue;
+            };
+            writeln!(
+                w,
+                "region [{:#x}, {:#x}) {} bytes",
+                region.data, region.end, len
+            )?;
+            if len == 0 {
+                continue;
+            }
+            let span = len.div_ceil(width);
+            let columns = len.div_ceil(span);
+            let column = |i: usize| {
+                let start = region.data + i * span;
+                (start, start.saturating_add(span).min(region.end))
+            };
+
+            write!(w, "  free |")?;
+            for i in 0..columns {
+                let (start, end) = column(i);
+                let free = self.free_bytes(&region, start, end);
+                let c = match free {
+                    0 => '#',
+                    _ if free == end - start => '.',
+                    _ if free * 2 >= end - start => ':',
+                    _ => '+',
+                };
+                w.write_char(c)?;
+            }
+            writeln!(w, "|")?;
+
+            for order in (0..self.order()).rev() {
+                if !self.has_blocks(&region, order) {
+                    continue;
+                }
+                write!(w, "  {:>4} |", order)?;
+                for i in 0..columns {
+                    let (start, end) = column(i);
+                    let c = if self.overlaps(&region, order, start, end) {
+                        '-'
+                    } else {
+                        ' '
+                    };
+                    w.write_char(c)?;
+                }
+                writeln!(w, "|")?;
+            }
+        }
+        Ok(())
+    }
+
+    /// 以 SVG 格式绘制每个区域的碎片分布图，布局与 [`HeapDump::render_ascii`] 相同。
+    /// 空闲块为绿色，已分配的内存为红色，鼠标悬停在空闲块上时显示其偏移量和大小
+    pub fn render_svg(&self, w: &mut impl fmt::Write) -> fmt::Result {
+        // 每个区域占用标题、free 以及每一级有空闲块的链表各一行，区域之间空一行
+        let rows: usize = self
+            .regions()
+            .map(|region| {
+                let orders = (0..self.order())
+                    .filter(|&order| self.has_blocks(&region, order))
+                    .count();
+                orders + 3
+            })
+            .sum();
+        writeln!(
+            w,
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="{}" height="{}" font-family="monospace" font-size="{}">"#,
+            SVG_LABEL + SVG_WIDTH,
+            rows * SVG_ROW,
+            SVG_ROW - 4
+        )?;
+
+        let mut y = 0;
+        for region in self.regions() {
+            // 区域的边界颠倒时只输出标题，与空区域占用相同的行数
+            let len = region.end.checked_sub(region.data);
+            match len {
+                Some(len) => writeln!(
+                    w,
+                    r#"<text x="0" y="{}">region [{:#x}, {:#x}) {} bytes</text>"#,
+                    y + SVG_ROW - 3,
+                    region.data,
+                    region.end,
+                    len
+                )?,
+                None => writeln!(
+                    w,
+                    r#"<text x="0" y="{}">region [{:#x}, {:#x}) invalid</text>"#,
+                    y + SVG_ROW - 3,
+                    region.data,
+                    region.end
+                )?,
+            }
+            y += SVG_ROW;
+            let len = len.unwrap_or(0);
+            if len == 0 {
+                y += 2 * SVG_ROW;
+                continue;
+            }
+            let x = |offset: usize| {
+                SVG_LABEL as f64 + (offset - region.data) as f64 * SVG_WIDTH as f64 / len as f64
+            };
+            let block = |w: &mut dyn fmt::Write, y: usize, start: usize, order: usize| {
+                let end = block_end(start, order).min(region.end);
+                writeln!(
+                    w,
+                    r#"<rect x="{:.2}" y="{}" width="{:.2}" height="{}" fill="{}"><title>{:#x} order {}</title></rect>"#,
+                    x(start),
+                    y,
+                    x(end) - x(start),
+                    SVG_ROW - 2,
+                    SVG_FREE,
+                    start,
+                    order
+                )
+            };
+
+            writeln!(
+                w,
+                r#"<text x="0" y="{}">free</text><rect x="{}" y="{}" width="{}" height="{}" fill="{}"/>"#,
+                y + SVG_ROW - 3,
+                SVG_LABEL,
+                y,
+                SVG_WIDTH,
+                SVG_ROW - 2,
+                SVG_ALLOCATED
+            )?;
+            for order in 0..self.order() {
+                for start in self.free_blocks(order) {
+                    if in_region(&region, start) {
+                        block(w, y, start, order)?;
+                    }
+                }
+            }
+            y += SVG_ROW;
+
+            for order in (0..self.order()).rev() {
+                if !self.has_blocks(&region, order) {
+                    continue;
+                }
+                writeln!(
+                    w,
+                    r#"<text x="0" y="{}">{}</text><rect x="{}" y="{}" width="{}" height="{}" fill="{}"/>"#,
+                    y + SVG_ROW - 3,
+                    order,
+                    SVG_LABEL,
+                    y,
+                    SVG_WIDTH,
+                    SVG_ROW - 2,
+                    SVG_EMPTY
+                )?;
+                for start in self.free_blocks(order) {
+                    if in_region(&region, start) {
+                        block(w, y, start, order)?;
+                    }
+                }
+                y += SVG_ROW;
+            }
+            y += SVG_ROW;
+        }
+        writeln!(w, "</svg>")
+    }
+}
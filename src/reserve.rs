@@ -0,0 +1,86 @@
+//! 为关键路径预留的块
+//!
+//! `LockFreeHeap::reserve` 预先从堆中分配 n 个满足同一 layout 的块，放入 [`Reservation`] 私有的链表。
+//! 之后从预留中分配不会访问堆的空闲链表，只要预留中还有块就一定成功，
+//! 适用于不能失败的中断处理等场景。预留中的块在堆看来已经分配，`Reservation` 被 drop 时归还给堆。
+//! 启用 `poison` 时，块进入和离开预留时与释放到堆和从堆分配一样检查红区和释放后写入。
+
+use core::alloc::Layout;
+use core::ptr::NonNull;
+
+#[cfg(feature = "poison")]
+use crate::error;
+#[cfg(feature = "poison")]
+use crate::imp::block_size;
+#[cfg(feature = "poison")]
+use crate::poison;
+use crate::sync::{AtomicUsize, Ordering};
+use crate::{LinkedList, LockFreeHeap};
+
+/// 从堆中预留的块，可以在多个线程之间共享
+pub struct Reservation<'a, const ORDER: usize> {
+    heap: &'a LockFreeHeap<ORDER>,
+    layout: Layout,
+    // 块的开头用作链表节点，与空闲链表相同
+    blocks: LinkedList,
+    remaining: AtomicUsize,
+}
+
+impl<'a, const ORDER: usize> Reservation<'a, ORDER> {
+    pub(crate) fn new(heap: &'a LockFreeHeap<ORDER>, layout: Layout) -> Self {
+        Self {
+            heap,
+            layout,
+            blocks: LinkedList::new(),
+            remaining: AtomicUsize::new(0),
+        }
+    }
+
+    /// 预留块的 layout
+    pub fn layout(&self) -> Layout {
+        self.layout
+    }
+
+    /// 预留中剩余的块数。与 `alloc`/`dealloc` 并发时可能暂时多于实际可以取出的块数
+    pub fn remaining(&self) -> usize {
+        self.remaining.load(Ordering::Relaxed)
+    }
+
+    /// 从预留中取出一个满足 `layout()` 的块，预留用完时返回 None。
+    /// 取出的块可以用 `dealloc` 放回预留，也可以用 `LockFreeHeap::dealloc_` 直接归还给堆
+    pub fn alloc(&self) -> Option<NonNull<u8>> {
+        let block = self.blocks.pop()? as usize;
+        // 块在 push 之前已经计数，因此计数不会小于 0
+        self.remaining.fetch_sub(1, Ordering::Relaxed);
+        #[cfg(feature = "poison")]
+        if let Err(err) =
+            unsafe { poison::check_alloc(block, block_size(self.layout), self.layout.size()) }
+        {
+            error::report(err);
+        }
+        Some(unsafe { NonNull::new_unchecked(block as *mut u8) })
+    }
+
+    /// 将从预留中取出的块放回预留，之后可以再次分配
+    pub fn dealloc(&self, ptr: NonNull<u8>) {
+        let block = ptr.as_ptr() as usize;
+        #[cfg(feature = "poison")]
+        {
+            let size = block_size(self.layout);
+            if let Err(err) = unsafe { poison::check_free(block, size, self.layout.size()) } {
+                error::report(err);
+            }
+            unsafe { poison::poison_free(block, size) };
+        }
+        self.remaining.fetch_add(1, Ordering::Relaxed);
+        unsafe { self.blocks.push(block as *mut _) };
+    }
+}
+
+impl<const ORDER: usize> Drop for Reservation<'_, ORDER> {
+    fn drop(&mut self) {
+        while let Some(ptr) = self.alloc() {
+            self.heap.dealloc_(ptr, self.layout);
+        }
+    }
+}
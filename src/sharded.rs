@@ -0,0 +1,120 @@
+//! 按 CPU 分片的堆
+//!
+//! [`ShardedHeap`] 包含 N 个互不相交的 `LockFreeHeap`，每个分片有自己的空闲链表头，
+//! 不同 CPU 上的分配落在不同的分片上，从而减少对同一组链表头的竞争。
+//! 分配时由调用者传入 CPU 编号或线程编号作为提示选择分片，该分片内存不足时依次从其它分片窃取；
+//! 释放时根据地址所在的区域找到分配该块的分片，因此块可以在任意 CPU 上释放。
+
+use core::alloc::Layout;
+use core::ptr::NonNull;
+
+use crate::imp::MIN_BLOCK_SIZE;
+use crate::LockFreeHeap;
+
+/// N 个分片组成的堆。释放时通过区域表查找分片，因此分片中的内存都必须记录在区域表中：
+/// 每个分片最多加入 `MAX_REGIONS` 个区域，启用 `heap-grow` 时区域表满后分片不再扩展
+pub struct ShardedHeap<const ORDER: usize, const N: usize> {
+    shards: [LockFreeHeap<ORDER>; N],
+}
+
+impl<const ORDER: usize, const N: usize> ShardedHeap<ORDER, N> {
+    const_fn! {
+        /// Create an empty heap
+        pub fn new() -> Self {
+            Self {
+                #[cfg(not(any(loom, shuttle)))]
+                shards: [const { LockFreeHeap::new() }; N],
+                #[cfg(any(loom, shuttle))]
+                shards: core::array::from_fn(|_| LockFreeHeap::new()),
+            }
+        }
+    }
+
+    /// 将 [start, start+size) 平均分为 N 段，分别加入各个分片
+    ///
+    /// # Safety
+    ///
+    /// 与 `LockFreeHeap::add_to_heap` 相同，这段内存必须可以读写且不被其它代码使用
+    pub unsafe fn init(&self, start: usize, size: usize) {
+        let part = (size / N) & !(MIN_BLOCK_SIZE - 1);
+        for (i, shard) in self.shards.iter().enumerate() {
+            let end = if i == N - 1 {
+                start + size
+            } else {
+                start + (i + 1) * part
+            };
+            Self::add_registered(shard, start + i * part, end);
+        }
+    }
+
+    /// 将 [start, end) 加入第 shard 个分片，例如将某个 NUMA 节点的内存加入该节点的 CPU 对应的分片。
+    ///
+    /// # Safety
+    ///
+    /// 与 `LockFreeHeap::add_to_heap` 相同，并且不能与已经加入任何分片的区域重叠。
+    /// 该分片的区域表已满时 panic
+    pub unsafe fn add_to_shard(&self, shard: usize, start: usize, end: usize) {
+        Self::add_registered(&self.shards[shard], start, end);
+    }
+
+    /// 加入区域并记录在分片的区域表中，没有记录的区域中的块释放时找不到所属的分片
+    unsafe fn add_registered(shard: &LockFreeHeap<ORDER>, start: usize, end: usize) {
+        if !shard.add_region(start, end) {
+            panic!("too many regions in shard");
+        }
+    }
+
+    /// 第 i 个分片，可用于查看各个分片的统计信息或转储
+    pub fn shard(&self, i: usize) -> &LockFreeHeap<ORDER> {
+        &self.shards[i]
+    }
+
+    /// 从第 hint % N 个分片分配，内存不足时依次尝试之后的分片，所有分片都不足时返回 None。
+    /// hint 通常为当前 CPU 的编号，同一 CPU 上的分配总是先尝试同一个分片
+    pub fn alloc_on(&self, hint: usize, layout: Layout) -> Option<NonNull<u8>> {
+        let first = hint % N;
+        (0..N)
+            .map(|i| &self.shards[(first + i) % N])
+            .find_map(|shard| shard.alloc_(layout).ok())
+    }
+
+    /// 将块归还给分配它的分片，可以在任意 CPU 上调用。
+    /// 分片中的块都位于区域表记录的区域中，因此 ptr 不属于任何分片时说明它不是由该堆分配的，此时 panic
+    pub fn dealloc_(&self, ptr: NonNull<u8>, layout: Layout) {
+        let addr = ptr.as_ptr() as usize;
+        match self.shards.iter().find(|shard| shard.owns(addr)) {
+            Some(shard) => shard.dealloc_(ptr, layout),
+            None => panic!("dealloc {:#x} not in any shard", addr),
+        }
+    }
+
+    /// 各分片的 `stats_alloc_user` 之和
+    pub fn stats_alloc_user(&self) -> usize {
+        self.shards
+            .iter()
+            .map(|shard| shard.stats_alloc_user())
+            .sum()
+    }
+
+    /// 各分片的 `stats_alloc_actual` 之和
+    pub fn stats_alloc_actual(&self) -> usize {
+        self.shards
+            .iter()
+            .map(|shard| shard.stats_alloc_actual())
+            .sum()
+    }
+
+    /// 各分片的 `stats_total_bytes` 之和
+    pub fn stats_total_bytes(&self) -> usize {
+        self.shards
+            .iter()
+            .map(|shard| shard.stats_total_bytes())
+            .sum()
+    }
+}
+
+impl<const ORDER: usize, const N: usize> Default for ShardedHeap<ORDER, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,228 @@
+//! 使用 shuttle 对链表和堆的并发操作进行随机调度测试
+//! 与 loom 不同，shuttle 不枚举所有交错执行，而是随机选取调度，因此可以测试线程和节点更多的场景。
+//! 失败时 shuttle 会打印用于重现该调度的种子。
+
+use crate::imp::MIN_BLOCK_SIZE;
+use crate::linked_list::ListNode;
+use crate::{LinkedList, LockFreeHeap};
+use core::alloc::Layout;
+use core::ptr::NonNull;
+use pi_pointer::GetDataBase;
+use shuttle::sync::Arc;
+use shuttle::thread;
+
+/// 每个测试随机选取的调度数量
+const ITERATIONS: usize = 1000;
+
+struct GetDataBaseImpl;
+
+#[crate_interface::impl_interface]
+impl GetDataBase for GetDataBaseImpl {
+    fn get_data_base() -> usize {
+        0
+    }
+}
+
+/// 按自身大小对齐的一段内存，由 `n` 个最小块组成。
+/// shuttle 的原子类型只能在测试内构造，因此每个最小块的开头都预先构造了一个链表节点，
+/// 使得切分与合并产生的任何块的起始地址处都是有效的节点。
+struct Arena {
+    start: usize,
+    layout: std::alloc::Layout,
+}
+
+impl Arena {
+    fn new(n: usize) -> Arc<Self> {
+        let size = n * MIN_BLOCK_SIZE;
+        let layout = std::alloc::Layout::from_size_align(size, size.next_power_of_two()).unwrap();
+        let start = unsafe { std::alloc::alloc(layout) } as usize;
+        assert_ne!(start, 0);
+        Self::reset_nodes(start, size);
+        Arc::new(Self { start, layout })
+    }
+
+    /// 在 [start, start + size) 中每个最小块的开头重新构造链表节点。
+    /// 节点由 shuttle 的原子类型组成，其中带有运行时状态，块的内容被覆盖后需要先重新构造节点才能释放
+    fn reset_nodes(start: usize, size: usize) {
+        for addr in (start..start + size).step_by(MIN_BLOCK_SIZE) {
+            unsafe { core::ptr::write(addr as *mut ListNode, ListNode::null()) };
+        }
+    }
+
+    fn addr(&self, i: usize) -> *mut () {
+        (self.start + i * MIN_BLOCK_SIZE) as *mut ()
+    }
+
+    fn end(&self) -> usize {
+        self.start + self.layout.size()
+    }
+}
+
+impl Drop for Arena {
+    fn drop(&mut self) {
+        unsafe { std::alloc::dealloc(self.start as *mut u8, self.layout) };
+    }
+}
+
+#[test]
+fn shuttle_push_pop_delete() {
+    shuttle::check_random(
+        || {
+            const N: usize = 6;
+            let arena = Arena::new(N);
+            let list = Arc::new(LinkedList::new());
+            unsafe {
+                for i in 0..N / 2 {
+                    list.push(arena.addr(i));
+                }
+            }
+            let pusher = {
+                let (arena, list) = (arena.clone(), list.clone());
+                thread::spawn(move || {
+                    for i in N / 2..N {
+                        unsafe { list.push(arena.addr(i)) };
+                    }
+                    Vec::new()
+                })
+            };
+            let popper = {
+                let list = list.clone();
+                thread::spawn(move || {
+                    (0..N / 2)
+                        .filter_map(|_| list.pop())
+                        .map(|item| item as usize)
+                        .collect::<Vec<_>>()
+                })
+            };
+            let deleter = {
+                let (arena, list) = (arena.clone(), list.clone());
+                thread::spawn(move || {
+                    (0..N)
+                        .step_by(2)
+                        .map(|i| arena.addr(i))
+                        .filter(|&item| list.delete(item))
+                        .map(|item| item as usize)
+                        .collect::<Vec<_>>()
+                })
+            };
+
+            // 每个节点恰好被取出一次
+            let mut taken: Vec<usize> = [pusher, popper, deleter]
+                .into_iter()
+                .flat_map(|h| h.join().unwrap())
+                .collect();
+            while let Some(item) = list.pop() {
+                taken.push(item as usize);
+            }
+            taken.sort();
+            assert_eq!(
+                taken,
+                (0..N).map(|i| arena.addr(i) as usize).collect::<Vec<_>>()
+            );
+        },
+        ITERATIONS,
+    );
+}
+
+#[test]
+fn shuttle_heap_alloc_dealloc() {
+    shuttle::check_random(
+        || {
+            const N: usize = 16;
+            let arena = Arena::new(N);
+            let heap = Arc::new(LockFreeHeap::<16>::new());
+            unsafe { heap.add_to_heap(arena.start, arena.end()) };
+
+            let handles: Vec<_> = (0..3)
+                .map(|t| {
+                    let heap = heap.clone();
+                    thread::spawn(move || {
+                        let layouts = [
+                            Layout::from_size_align(MIN_BLOCK_SIZE << (t % 2), 1).unwrap(),
+                            Layout::from_size_align(MIN_BLOCK_SIZE, 1).unwrap(),
+                        ];
+                        // 合并过程中的分配可能暂时失败，但分配到的块不能重叠
+                        let blocks: Vec<_> = layouts
+                            .iter()
+                            .filter_map(|&layout| heap.alloc_(layout).ok().map(|ptr| (ptr, layout)))
+                            .collect();
+                        for &(ptr, layout) in &blocks {
+                            unsafe { core::ptr::write_bytes(ptr.as_ptr(), t as u8, layout.size()) };
+                        }
+                        thread::yield_now();
+                        for &(ptr, layout) in &blocks {
+                            let data =
+                                unsafe { core::slice::from_raw_parts(ptr.as_ptr(), layout.size()) };
+                            assert!(data.iter().all(|&b| b == t as u8), "overlapping blocks");
+                            Arena::reset_nodes(ptr.as_ptr() as usize, layout.size());
+                            heap.dealloc_(ptr, layout);
+                        }
+                    })
+                })
+                .collect();
+            for h in handles {
+                h.join().unwrap();
+            }
+            assert_eq!(heap.stats_alloc_actual(), 0);
+            assert_eq!(heap.stats_alloc_user(), 0);
+
+            // 释放的块都已回到空闲链表
+            let block = Layout::from_size_align(MIN_BLOCK_SIZE, 1).unwrap();
+            let mut blocks = Vec::new();
+            while let Ok(ptr) = heap.alloc_(block) {
+                blocks.push(ptr.as_ptr() as usize);
+            }
+            blocks.sort();
+            assert_eq!(
+                blocks,
+                (0..N).map(|i| arena.addr(i) as usize).collect::<Vec<_>>()
+            );
+            for ptr in blocks {
+                heap.dealloc_(NonNull::new(ptr as *mut u8).unwrap(), block);
+            }
+        },
+        ITERATIONS,
+    );
+}
+
+/// 节点被取出后立即被复用，见 `loom_tests::loom_pop_reuse`
+#[test]
+#[ignore = "known bug: doc/无锁列表当前bug描述.md"]
+fn shuttle_pop_reuse() {
+    shuttle::check_random(
+        || {
+            const N: usize = 4;
+            let arena = Arena::new(N);
+            let lists = Arc::new([LinkedList::new(), LinkedList::new()]);
+            unsafe {
+                for i in 0..N {
+                    lists[0].push(arena.addr(i));
+                }
+            }
+            // 每个线程不断地从一个链表取出节点并放入另一个链表
+            let handles: Vec<_> = (0..2)
+                .map(|t| {
+                    let lists = lists.clone();
+                    thread::spawn(move || {
+                        for _ in 0..N {
+                            if let Some(node) = lists[t].pop() {
+                                unsafe { lists[1 - t].push(node) };
+                            }
+                        }
+                    })
+                })
+                .collect();
+            for h in handles {
+                h.join().unwrap();
+            }
+            let mut count = 0;
+            for list in lists.iter() {
+                while list.pop().is_some() {
+                    count += 1;
+                }
+            }
+            assert_eq!(count, N);
+        },
+        ITERATIONS,
+    );
+}
@@ -0,0 +1,100 @@
+//! 使用静态内存、在第一次分配时初始化的堆
+//!
+//! `LockFreeHeap::new` 可以在编译期求值，但加入内存区域需要在链表中写入节点，只能在运行时进行。
+//! 作为 `#[global_allocator]` 使用时，std 在 `main` 之前就会分配内存，因此以前需要借助 `ctor` 提前调用 `init`。
+//! [`StaticHeap`] 记录一块静态内存，在第一次使用堆时将其加入堆，多个线程同时第一次使用时只有一个线程进行初始化，
+//! 其它线程等待初始化完成。
+
+use core::alloc::{GlobalAlloc, Layout};
+use core::fmt;
+use core::ops::Deref;
+
+use spin::Once;
+
+use crate::LockFreeHeap;
+
+/// 使用一块静态内存的堆，通过 `Deref` 使用内部的 `LockFreeHeap`，第一次使用时初始化
+///
+/// ```ignore
+/// #[global_allocator]
+/// static HEAP: StaticHeap<32> = StaticHeap::with_static(unsafe { &mut *&raw mut SPACE });
+/// ```
+///
+/// 也可以使用 [`static_heap!`](crate::static_heap) 同时声明堆和它使用的内存
+pub struct StaticHeap<const ORDER: usize> {
+    heap: LockFreeHeap<ORDER>,
+    // 编译期不能将指针转换为整数，因此保存指针
+    space: *mut u8,
+    size: usize,
+    init: Once,
+}
+
+unsafe impl<const ORDER: usize> Send for StaticHeap<ORDER> {}
+unsafe impl<const ORDER: usize> Sync for StaticHeap<ORDER> {}
+
+impl<const ORDER: usize> StaticHeap<ORDER> {
+    const_fn! {
+        /// 使用 space 作为堆的内存，此时不会访问 space
+        pub fn with_static(space: &'static mut [u8]) -> Self {
+            Self {
+                heap: LockFreeHeap::new(),
+                size: space.len(),
+                space: space.as_mut_ptr(),
+                init: Once::new(),
+            }
+        }
+    }
+
+    /// 内部的堆，第一次调用时将静态内存加入堆
+    pub fn heap(&self) -> &LockFreeHeap<ORDER> {
+        self.init
+            .call_once(|| unsafe { self.heap.init(self.space as usize, self.size) });
+        &self.heap
+    }
+}
+
+impl<const ORDER: usize> Deref for StaticHeap<ORDER> {
+    type Target = LockFreeHeap<ORDER>;
+
+    fn deref(&self) -> &LockFreeHeap<ORDER> {
+        self.heap()
+    }
+}
+
+impl<const ORDER: usize> fmt::Debug for StaticHeap<ORDER> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        self.heap().fmt(fmt)
+    }
+}
+
+unsafe impl<const ORDER: usize> GlobalAlloc for StaticHeap<ORDER> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        self.heap().alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        // 能够释放的块一定来自已经初始化的堆
+        self.heap.dealloc(ptr, layout)
+    }
+}
+
+/// 声明一个 [`StaticHeap`] 以及它使用的大小为 size 字节、按页对齐的静态内存
+///
+/// ```ignore
+/// static_heap! {
+///     #[global_allocator]
+///     static HEAP: StaticHeap<32> = 128 * 1024 * 1024;
+/// }
+/// ```
+#[macro_export]
+macro_rules! static_heap {
+    ($(#[$attr:meta])* $vis:vis static $name:ident: StaticHeap<$order:tt> = $size:expr;) => {
+        $(#[$attr])*
+        $vis static $name: $crate::StaticHeap<$order> = {
+            #[repr(C, align(4096))]
+            struct Space([u8; $size]);
+            static mut SPACE: Space = Space([0; $size]);
+            $crate::StaticHeap::with_static(unsafe { &mut (*&raw mut SPACE).0 })
+        };
+    };
+}
@@ -0,0 +1,60 @@
+//! 分片的统计计数器
+//!
+//! 每次分配和释放都要修改统计信息。计数器分为多个按缓存行对齐的分片，读取时将所有分片相加。
+//! 启用 `percpu-stats` 时按 [`CpuHint`](crate::CpuHint) 给出的 CPU 编号选择分片，
+//! 同一个块的分配与释放可能落在不同的分片上，单个分片的值可能回绕，但各分片按回绕算术相加的结果仍然正确。
+//! 未启用时按块地址选择分片，同一个块的分配与释放总是落在同一个分片上。
+
+use crate::cache_padded::CachePadded;
+use crate::sync::{AtomicUsize, Ordering};
+
+/// 分片数量
+const SHARDS: usize = 8;
+
+pub(crate) struct ShardedCounter {
+    shards: [CachePadded<AtomicUsize>; SHARDS],
+}
+
+impl ShardedCounter {
+    const_fn! {
+        pub(crate) fn new() -> Self {
+            Self {
+                #[cfg(not(any(loom, shuttle)))]
+                shards: [const { CachePadded::new(AtomicUsize::new(0)) }; SHARDS],
+                #[cfg(any(loom, shuttle))]
+                shards: core::array::from_fn(|_| CachePadded::new(AtomicUsize::new(0))),
+            }
+        }
+    }
+
+    /// 根据当前 CPU 选择分片
+    #[cfg(feature = "percpu-stats")]
+    fn shard(&self, _addr: usize) -> &AtomicUsize {
+        &self.shards[crate::percpu::current_cpu() % SHARDS]
+    }
+
+    /// 根据块地址选择分片
+    #[cfg(not(feature = "percpu-stats"))]
+    fn shard(&self, addr: usize) -> &AtomicUsize {
+        // 块地址按块大小对齐，低位总是 0，因此用乘法散列混合高位
+        const MULTIPLIER: usize = 0x9e37_79b9_7f4a_7c15_u64 as usize;
+        let index = addr.wrapping_mul(MULTIPLIER) >> (usize::BITS - SHARDS.trailing_zeros());
+        &self.shards[index]
+    }
+
+    /// 统计信息与链表之间没有数据依赖，只需保证计数本身的原子性
+    pub(crate) fn add(&self, addr: usize, value: usize) {
+        self.shard(addr).fetch_add(value, Ordering::Relaxed);
+    }
+
+    pub(crate) fn sub(&self, addr: usize, value: usize) {
+        self.shard(addr).fetch_sub(value, Ordering::Relaxed);
+    }
+
+    /// 各分片之和。与修改并发时，结果不一定对应某一时刻的准确值
+    pub(crate) fn sum(&self) -> usize {
+        self.shards.iter().fold(0, |sum, shard| {
+            sum.wrapping_add(shard.load(Ordering::Relaxed))
+        })
+    }
+}
@@ -0,0 +1,43 @@
+//! 原子类型的抽象层
+//!
+//! 链表与堆使用的原子类型都从这里导入。
+//! 以 `RUSTFLAGS="--cfg loom"` 编译时替换为 loom 提供的类型，从而可以用 loom 对并发操作进行模型检查；
+//! 以 `RUSTFLAGS="--cfg shuttle"` 编译时替换为 shuttle 提供的类型，用随机调度测试规模更大的场景。
+//! 这两种原子类型都不能在常量上下文中构造，因此这种情况下各个构造函数也不再是 `const fn`。
+
+#[cfg(loom)]
+pub(crate) use loom::{
+    hint::spin_loop,
+    sync::atomic::{AtomicPtr, AtomicUsize, Ordering},
+};
+
+#[cfg(shuttle)]
+pub(crate) use shuttle::{
+    hint::spin_loop,
+    sync::atomic::{AtomicPtr, AtomicUsize, Ordering},
+};
+
+#[cfg(not(any(loom, shuttle)))]
+pub(crate) use core::{
+    hint::spin_loop,
+    sync::atomic::{AtomicPtr, AtomicUsize, Ordering},
+};
+
+/// 定义一个在 loom 或 shuttle 下不是 `const fn` 的构造函数
+macro_rules! const_fn {
+    ($(#[$attr:meta])* $vis:vis fn $($rest:tt)*) => {
+        #[cfg(not(any(loom, shuttle)))]
+        $(#[$attr])* $vis const fn $($rest)*
+        #[cfg(any(loom, shuttle))]
+        $(#[$attr])* $vis fn $($rest)*
+    };
+}
+
+/// 故障注入点，启用 `fault-injection` 时调用使用者实现的 `FaultInjector`，
+/// 否则展开为空，参数不会被求值
+macro_rules! fault_point {
+    ($point:expr) => {
+        #[cfg(feature = "fault-injection")]
+        crate::fault::inject($point);
+    };
+}
@@ -0,0 +1,187 @@
+//! 检查转储是否满足堆的不变量
+//!
+//! 堆没有并发操作时，转储应当满足：
+//! - 每个空闲块按其大小对齐，并且位于某个区域的可分配部分中
+//! - 空闲块之间互不重叠
+//! - 互为伙伴的两个空闲块已经被合并
+//! - 空闲块的总大小与已分配的大小之和等于堆的总大小，用户请求的大小不超过已分配的大小
+//!
+//! 检查不需要分配内存，空闲块之间两两比较，因此耗时与空闲块数的平方成正比。
+
+use core::fmt;
+
+use crate::HeapDump;
+
+/// 转储中违反堆的不变量之处，均为偏移量
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Violation {
+    /// 空闲块没有按其大小对齐
+    Misaligned { block: usize, order: usize },
+    /// 空闲块不在任何区域的可分配部分中
+    OutsideRegions { block: usize, order: usize },
+    /// 两个空闲块重叠
+    Overlap {
+        block: usize,
+        order: usize,
+        other: usize,
+        other_order: usize,
+    },
+    /// 互为伙伴的两个空闲块没有被合并
+    UnmergedBuddies {
+        block: usize,
+        buddy: usize,
+        order: usize,
+    },
+    /// 空闲块的总大小与已分配的大小之和不等于堆的总大小
+    Accounting {
+        free: usize,
+        allocated: usize,
+        total: usize,
+    },
+    /// 用户请求的大小超过已分配的大小
+    UserExceedsAllocated { user: usize, allocated: usize },
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Violation::Misaligned { block, order } => write!(
+                f,
+                "block {:#x} (order {}) is not aligned to its size",
+                block, order
+            ),
+            Violation::OutsideRegions { block, order } => write!(
+                f,
+                "block {:#x} (order {}) is outside all regions",
+                block, order
+            ),
+            Violation::Overlap {
+                block,
+                order,
+                other,
+                other_order,
+            } => write!(
+                f,
+                "block {:#x} (order {}) overlaps block {:#x} (order {})",
+                block, order, other, other_order
+            ),
+            Violation::UnmergedBuddies {
+                block,
+                buddy,
+                order,
+            } => write!(
+                f,
+                "buddies {:#x} and {:#x} (order {}) are both free",
+                block, buddy, order
+            ),
+            Violation::Accounting {
+                free,
+                allocated,
+                total,
+            } => write!(
+                f,
+                "free {} + allocated {} != total {}",
+                free, allocated, total
+            ),
+            Violation::UserExceedsAllocated { user, allocated } => {
+                write!(f, "user {} > allocated {}", user, allocated)
+            }
+        }
+    }
+}
+
+impl HeapDump<'_> {
+    /// 检查转储是否满足堆的不变量，返回发现的第一个违反之处
+    pub fn verify(&self) -> Result<(), Violation> {
+        let mut first = None;
+        self.violations(|violation| {
+            first.get_or_insert(violation);
+        });
+        first.map_or(Ok(()), Err)
+    }
+
+    /// 对转储中每个违反堆的不变量之处调用 f
+    pub fn violations(&self, mut f: impl FnMut(Violation)) {
+        let base = self.base();
+        let in_region = |start: usize, end: usize| {
+            self.regions()
+                .any(|region| region.data <= start && end <= region.end)
+        };
+
+        for (i, (block, order)) in self.blocks().enumerate() {
+            // 大小超出地址空间的块不可能对齐，也不可能位于区域中
+            let Some(size) = block_size(order) else {
+                f(Violation::Misaligned { block, order });
+                f(Violation::OutsideRegions { block, order });
+                continue;
+            };
+            let end = block.saturating_add(size);
+            if !block.wrapping_add(base).is_multiple_of(size) {
+                f(Violation::Misaligned { block, order });
+            }
+            if !in_region(block, end) {
+                f(Violation::OutsideRegions { block, order });
+            }
+
+            // 每对重叠的块只报告一次，重复出现的块也视为重叠
+            for (other, other_order) in self.blocks().skip(i + 1) {
+                let other_end = other.saturating_add(block_size(other_order).unwrap_or(usize::MAX));
+                if other < end && block < other_end {
+                    f(Violation::Overlap {
+                        block,
+                        order,
+                        other,
+                        other_order,
+                    });
+                }
+            }
+
+            // 伙伴关系由实际地址决定
+            if order + 1 >= self.order() {
+                continue;
+            }
+            let buddy = (block.wrapping_add(base) ^ size).wrapping_sub(base);
+            if block < buddy
+                && in_region(block, buddy.saturating_add(size))
+                && self.free_blocks(order).any(|other| other == buddy)
+            {
+                f(Violation::UnmergedBuddies {
+                    block,
+                    buddy,
+                    order,
+                });
+            }
+        }
+
+        let stats = self.stats();
+        let free = self.blocks().fold(0usize, |free, (_, order)| {
+            free.saturating_add(block_size(order).unwrap_or(usize::MAX))
+        });
+        if free.checked_add(stats.allocated) != Some(stats.total) {
+            f(Violation::Accounting {
+                free,
+                allocated: stats.allocated,
+                total: stats.total,
+            });
+        }
+        if stats.user > stats.allocated {
+            f(Violation::UserExceedsAllocated {
+                user: stats.user,
+                allocated: stats.allocated,
+            });
+        }
+    }
+
+    /// 所有空闲块及其级数
+    fn blocks(&self) -> impl Iterator<Item = (usize, usize)> + '_ {
+        (0..self.order()).flat_map(|order| self.free_blocks(order).map(move |block| (block, order)))
+    }
+}
+
+/// 大小为 2^order 的块的字节数，超出 usize 时为 None
+fn block_size(order: usize) -> Option<usize> {
+    u32::try_from(order)
+        .ok()
+        .and_then(|order| 1usize.checked_shl(order))
+}
@@ -0,0 +1,101 @@
+//! 内存水位通知
+//!
+//! 启用 `watermarks` 后，需要像 `GetDataBase` 一样，
+//! 使用 `crate_interface::impl_interface` 实现 [`MemoryPressure`]。
+//! 通过 `LockFreeHeap::set_watermarks` 设置空闲字节数（`stats_total_bytes() - stats_alloc_actual()`）的低水位与高水位：
+//! 空闲字节数降到低水位以下时通知 [`Pressure::Low`]，之后回到高水位及以上时通知 [`Pressure::Normal`]。
+//! 两次通知之间必须越过另一条水位线，因此空闲字节数在某条水位线附近波动时不会重复通知。
+//!
+//! 每次分配、释放以及堆大小改变之后检查水位，状态通过 CAS 切换，每次越过水位线只有一个线程发出通知。
+//! 与分配和释放并发时，空闲字节数不一定是某一时刻的准确值，状态可能在下一次检查时才切换，
+//! 相邻两次通知的回调也可能并发执行。
+
+use crate::sync::{AtomicUsize, Ordering};
+
+/// 堆的内存压力状态
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Pressure {
+    /// 空闲字节数低于低水位
+    Low,
+    /// 初始状态，或空闲字节数低于低水位之后回到高水位及以上
+    Normal,
+}
+
+/// 内存压力状态改变时的回调函数，由使用者实现
+#[crate_interface::def_interface]
+pub trait MemoryPressure {
+    /// free 为检查时的空闲字节数。该函数在分配或释放的过程中调用，不应在其中使用同一个堆分配内存
+    fn on_memory_pressure(pressure: Pressure, free: usize);
+}
+
+const NORMAL: usize = 0;
+const LOW: usize = 1;
+
+/// 水位与当前状态
+pub(crate) struct Watermarks {
+    low: AtomicUsize,
+    high: AtomicUsize,
+    state: AtomicUsize,
+}
+
+impl Watermarks {
+    const_fn! {
+        pub(crate) fn new() -> Self {
+            Self {
+                low: AtomicUsize::new(0),
+                high: AtomicUsize::new(0),
+                state: AtomicUsize::new(NORMAL),
+            }
+        }
+    }
+
+    pub(crate) fn set(&self, low: usize, high: usize) {
+        assert!(
+            low <= high,
+            "low watermark {} > high watermark {}",
+            low,
+            high
+        );
+        self.low.store(low, Ordering::Relaxed);
+        self.high.store(high, Ordering::Relaxed);
+    }
+
+    pub(crate) fn pressure(&self) -> Pressure {
+        match self.state.load(Ordering::Relaxed) {
+            LOW => Pressure::Low,
+            _ => Pressure::Normal,
+        }
+    }
+
+    /// 根据空闲字节数切换状态，切换成功时通知使用者。
+    /// 低水位为 0 时不可能进入 Low，此时不计算空闲字节数
+    pub(crate) fn update(&self, free: impl FnOnce() -> usize) {
+        let (from, to, pressure, free) = match self.state.load(Ordering::Relaxed) {
+            NORMAL => {
+                let low = self.low.load(Ordering::Relaxed);
+                if low == 0 {
+                    return;
+                }
+                let free = free();
+                if free >= low {
+                    return;
+                }
+                (NORMAL, LOW, Pressure::Low, free)
+            }
+            _ => {
+                let free = free();
+                if free < self.high.load(Ordering::Relaxed) {
+                    return;
+                }
+                (LOW, NORMAL, Pressure::Normal, free)
+            }
+        };
+        if self
+            .state
+            .compare_exchange(from, to, Ordering::Relaxed, Ordering::Relaxed)
+            .is_ok()
+        {
+            crate_interface::call_interface!(MemoryPressure::on_memory_pressure, pressure, free);
+        }
+    }
+}